@@ -13,56 +13,322 @@ fn main() {
 
 #[cfg(target_os = "windows")]
 mod cli {
-    use lib::find_wsl_ip;
     use main as lib;
 
+    const DEFAULT_HISTORY_LIMIT: usize = 20;
+
     fn show_help() {
         print!(
             "wsl2-ip-host {}
 
-Usage: wsl2-ip-host [-d distro] [-n <host-name>] ...
+Usage: wsl2-ip-host [-c <settings>] [-d distro] [-n <host-name>] ...
+       wsl2-ip-host remove [-c <settings>] [-n <host-name>] ...
+       wsl2-ip-host status [-c <settings>] [-n <host-name>] ...
+       wsl2-ip-host ip [-d distro] [-i <interface>] [-4 | -6] [--all]
+       wsl2-ip-host windows-host [-d distro] [-n <host-name>] ...
+       wsl2-ip-host dns [-d distro] [-n <host-name>] ... [--listen <addr>] [--upstream <addr>]
+       wsl2-ip-host history [-d distro] [--limit <n>]
+       wsl2-ip-host wslconfig [get [<setting>] | set <setting> <value> | unset <setting>]
 
 Uses wsl to retrieve the IP address of a wsl vm and writes it to the windows hosts
 file.
 
+Commands:
+ip                          Print the IP address(es) of the wsl vm without writing
+                            anything.
+windows-host                Write the windows host IP, as seen from the distro, into
+                            the distro's /etc/hosts [default name: {}]
+remove                      Take everything written by this tool out of the hosts file
+                            and the other files it writes to. A rendered template is
+                            deleted, unless it was given a path of its own, and the
+                            port forwards it added are deleted.
+dns                         Answer DNS queries for the host names with the live IP
+                            instead of writing the hosts file. Runs until stopped.
+status                      Print our lines in the hosts file, other programs' sections
+                            of it, such as Docker Desktop's, and lines of yours that
+                            also map the host names.
+history                     Print the most recent writes, oldest first, from the
+                            journal kept in %USERPROFILE%\\{}.
+wslconfig                   Print or change the settings in %USERPROFILE%\\{},
+                            e.g. `wslconfig set wsl2.memory 8GB`. A setting is
+                            section.key, the section being wsl2 when left out.
+                            Comments and the order of the file are kept.
+
 Options:
+-c, --config <settings>     Read the settings saved by the tray app, e.g.
+                            %USERPROFILE%\\{}, including the hooks run after
+                            writing. Options on the command line take precedence.
 -d, --distro <distro>       WSL distro name -d passed to wsl.exe. Falls back to your
                             default distro if omitted.
 -n, --name <host-name>      Host name to associate the ip to [default: {}]
                             this option can be passed multiple times to add more than one
-                            host name.
+                            host name. `*.domain` stands for every name below domain,
+                            see --subdomain.
+-S, --subdomain <label>     Label a `*.domain` name expands to when writing the hosts
+                            file, e.g. -S api -S web. The dns command answers for any
+                            label without it. Can be passed multiple times.
+-p, --forward <forward>     Keep a netsh portproxy rule from windows to the distro in
+                            step with the IP, as [address:]port[:connect-port]. The
+                            listen address defaults to 0.0.0.0. Can be passed multiple
+                            times.
+--ssh-host <alias>          Keep a `Host <alias>` entry with the IP as its HostName in
+                            ~/.ssh/config. Can be passed multiple times.
+--env-file <path>           Keep a WSL_IP=<ip> line in a dotenv file.
+--status-file <path>        Write the IP, names and distro to a json file.
+--template <path>           Render a template with {{{{ip}}}}, {{{{distro}}}} and {{{{names}}}}
+                            placeholders to the same path without its .tmpl extension.
+--on-conflict <policy>      What to do when a line we don't manage already maps one of
+                            the host names: warn and write ours too, skip the name,
+                            or comment-out the other line until remove [default: warn]
+--line-style <style>        Write a hosts line per name, or grouped to put up to 9 names
+                            on each line [default: per-name]
+--static <ip=name[,name]>   Keep a fixed line such as 127.0.0.1 local.myapp.test with
+                            ours in the hosts file. Can be passed multiple times.
+--source <name=source>      Point a host name somewhere other than the distro: at
+                            distro:<other distro>, windows-host, static:<ip> or the
+                            first address printed by command:<shell snippet> run in
+                            the distro. Can be passed multiple times.
+--mirrored <behaviour>      With networkingMode=mirrored in .wslconfig, point the host
+                            names at 127.0.0.1 with loopback, or skip writing
+                            [default: loopback]
+-i, --interface <name>      Interface to read the address from [default: {}]
+-4, -6                      Address family to print [default: -4]
+-a, --all                   Print the IP of every installed distro as `<distro> <ip>`,
+                            leaving stopped ones stopped unless --on-stopped start
+-t, --timeout <seconds>     Give up on a distro that takes longer than this to answer
+                            [default: {}]
+--on-stopped <behaviour>    With the distro stopped, start it and wait for its
+                            address, or fail without starting it [default: start]
+--boot-timeout <seconds>    Give up on a stopped distro that takes longer than this
+                            to start and get an address [default: {}]
+-s, --strategy <strategy>   How to look up the address, tried in the order given until
+                            one succeeds. One of ip-json, ip-brief, proc-net, hostname,
+                            fib-trie or ifconfig [default: all of them in that order]
+-l, --listen <addr>         Address the dns command listens on for udp and tcp
+                            [default: {}]
+-u, --upstream <addr>       Server other dns queries are forwarded to, they are refused
+                            when omitted
+--limit <n>                 Number of journal entries history prints [default: {}]
+-v, --verbose               Log debug messages too, and print them to stderr
+--log-file <path>           Log to this file instead of
+                            %USERPROFILE%\\{}\\wsl2-ip-host-cli.log, which is
+                            rotated at 1 MB.
 -h, --help                  Display help text
 ",
             lib::VERSION,
-            lib::DEFAULT_HOST
+            lib::DEFAULT_WINDOWS_HOST,
+            lib::journal::JOURNAL_NAME,
+            lib::wslconfig::WSLCONFIG_NAME,
+            lib::settings::SAVE_NAME,
+            lib::DEFAULT_HOST,
+            lib::DEFAULT_INTERFACE,
+            lib::DEFAULT_TIMEOUT.as_secs(),
+            lib::DEFAULT_BOOT_TIMEOUT.as_secs(),
+            lib::dns::DEFAULT_LISTEN,
+            DEFAULT_HISTORY_LIMIT,
+            lib::logging::LOG_DIR
         );
     }
 
+    #[derive(Debug, PartialEq)]
+    enum Command {
+        Write,
+        Ip,
+        WindowsHost,
+        Dns,
+        Remove,
+        History,
+        Status,
+        WslConfig,
+    }
+
     #[derive(Debug)]
     struct App {
         help: bool,
+        command: Command,
+        config: Option<String>,
         names: Vec<String>,
+        subdomains: Vec<String>,
+        forwards: Vec<lib::PortForward>,
+        ssh_hosts: Vec<String>,
+        outputs: Vec<lib::Output>,
+        on_conflict: Option<lib::OnConflict>,
+        line_style: Option<lib::LineStyle>,
+        static_entries: Vec<lib::Mapping>,
+        sources: Vec<lib::NameSource>,
+        mirrored: Option<lib::OnMirrored>,
+        on_stopped: Option<lib::OnStopped>,
         distro: Option<String>,
+        query: lib::IpQuery,
+        all: bool,
+        timeout: std::time::Duration,
+        boot_timeout: std::time::Duration,
+        strategies: Vec<lib::Strategy>,
+        listen: Option<String>,
+        upstream: Option<String>,
+        limit: usize,
+        /// arguments after the command that aren't options
+        words: Vec<String>,
+        log: lib::logging::Options,
+        errors: Vec<String>,
     }
 
     impl App {
         fn apply(&mut self, option: &str, value: Option<String>) {
             match option {
+                "-c" | "--config" if value.is_some() => self.config = value,
                 "-d" | "--distro" if value.is_some() => self.distro = value,
                 "-n" | "--name" if value.is_some() => self.names.push(value.unwrap()),
+                "-i" | "--interface" if value.is_some() => self.query.interface = value.unwrap(),
+                "-t" | "--timeout" if value.is_some() => match seconds(&value.unwrap()) {
+                    Ok(timeout) => self.timeout = timeout,
+                    Err(e) => self.errors.push(e),
+                },
+                "--boot-timeout" if value.is_some() => match seconds(&value.unwrap()) {
+                    Ok(timeout) => self.boot_timeout = timeout,
+                    Err(e) => self.errors.push(e),
+                },
+                "-s" | "--strategy" if value.is_some() => match value.unwrap().parse() {
+                    Ok(strategy) => self.strategies.push(strategy),
+                    Err(e) => self.errors.push(e),
+                },
+                "-S" | "--subdomain" if value.is_some() => self.subdomains.push(value.unwrap()),
+                "-p" | "--forward" if value.is_some() => match value.unwrap().parse() {
+                    Ok(forward) => self.forwards.push(forward),
+                    Err(e) => self.errors.push(e),
+                },
+                "--ssh-host" if value.is_some() => self.ssh_hosts.push(value.unwrap()),
+                "--env-file" if value.is_some() => {
+                    self.outputs
+                        .push(lib::Output::EnvFile(lib::target::EnvFile {
+                            path: value.unwrap(),
+                            key: lib::target::DEFAULT_ENV_KEY.to_owned(),
+                        }))
+                }
+                "--template" if value.is_some() => {
+                    self.outputs
+                        .push(lib::Output::Template(lib::template::TemplateFile {
+                            template: value.unwrap(),
+                            path: None,
+                        }))
+                }
+                "--status-file" if value.is_some() => {
+                    self.outputs
+                        .push(lib::Output::StatusFile(lib::target::StatusFile {
+                            path: value.unwrap(),
+                        }))
+                }
+                "--on-conflict" if value.is_some() => match value.unwrap().parse() {
+                    Ok(policy) => self.on_conflict = Some(policy),
+                    Err(e) => self.errors.push(e),
+                },
+                "--line-style" if value.is_some() => match value.unwrap().parse() {
+                    Ok(style) => self.line_style = Some(style),
+                    Err(e) => self.errors.push(e),
+                },
+                "--static" if value.is_some() => match value.unwrap().parse() {
+                    Ok(entry) => self.static_entries.push(entry),
+                    Err(e) => self.errors.push(e),
+                },
+                "--source" if value.is_some() => match value.unwrap().parse() {
+                    Ok(source) => self.sources.push(source),
+                    Err(e) => self.errors.push(e),
+                },
+                "--mirrored" if value.is_some() => match value.unwrap().parse() {
+                    Ok(mirrored) => self.mirrored = Some(mirrored),
+                    Err(e) => self.errors.push(e),
+                },
+                "--on-stopped" if value.is_some() => match value.unwrap().parse() {
+                    Ok(on_stopped) => self.on_stopped = Some(on_stopped),
+                    Err(e) => self.errors.push(e),
+                },
+                "-l" | "--listen" if value.is_some() => self.listen = value,
+                "-u" | "--upstream" if value.is_some() => self.upstream = value,
+                "--limit" if value.is_some() => {
+                    let value = value.unwrap();
+                    match value.parse() {
+                        Ok(limit) => self.limit = limit,
+                        Err(_) => self.errors.push(format!(
+                            "Invalid limit {}, expected a number of entries",
+                            value
+                        )),
+                    }
+                }
                 "-n" | "--name" => (),
                 _ => (),
             };
         }
+
+        fn discovery(&self) -> lib::Discovery {
+            let mut discovery = lib::Discovery::new();
+            discovery.query = self.query.clone();
+            discovery.timeout = self.timeout;
+            discovery.boot_timeout = self.boot_timeout;
+            if let Some(on_stopped) = self.on_stopped {
+                discovery.on_stopped = on_stopped;
+            }
+            if false == self.strategies.is_empty() {
+                discovery.strategies = self.strategies.clone();
+            }
+            discovery
+        }
+
+        /// whether the command works on the names and targets in the settings
+        fn writes(&self) -> bool {
+            self.command == Command::Write
+                || self.command == Command::Remove
+                || self.command == Command::Status
+        }
+
+        fn flag(&mut self, flag: &str) {
+            match flag {
+                "-4" => self.query.family = lib::Family::V4,
+                "-6" => self.query.family = lib::Family::V6,
+                "-a" | "--all" => self.all = true,
+                _ => (),
+            };
+        }
+    }
+
+    fn seconds(value: &str) -> Result<std::time::Duration, String> {
+        value
+            .parse()
+            .map(std::time::Duration::from_secs)
+            .map_err(|_| format!("Invalid timeout {}, expected a number of seconds", value))
     }
 
     fn parse_args() -> App {
-        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        let mut log = lib::logging::Options::from_args(&mut args);
+        log.stderr = log.verbose;
 
         let mut cli = App {
             help: true,
+            command: Command::Write,
+            config: None,
             names: vec![],
+            subdomains: vec![],
+            forwards: vec![],
+            ssh_hosts: vec![],
+            outputs: vec![],
+            on_conflict: None,
+            line_style: None,
+            static_entries: vec![],
+            sources: vec![],
+            mirrored: None,
+            on_stopped: None,
             distro: None,
+            query: lib::IpQuery::new(),
+            all: false,
+            timeout: lib::DEFAULT_TIMEOUT,
+            boot_timeout: lib::DEFAULT_BOOT_TIMEOUT,
+            strategies: vec![],
+            listen: None,
+            upstream: None,
+            limit: DEFAULT_HISTORY_LIMIT,
+            words: vec![],
+            log,
+            errors: vec![],
         };
 
         if args.iter().any(|a| &"-h" == a || &"--help" == a) {
@@ -71,11 +337,62 @@ Options:
             cli.help = false;
         }
 
-        let options = ["-d", "--distro", "-n", "--name"];
+        let options = [
+            "-c",
+            "--config",
+            "-d",
+            "--distro",
+            "-n",
+            "--name",
+            "-i",
+            "--interface",
+            "-t",
+            "--timeout",
+            "--on-stopped",
+            "--boot-timeout",
+            "-s",
+            "--strategy",
+            "-S",
+            "--subdomain",
+            "-p",
+            "--forward",
+            "--ssh-host",
+            "--env-file",
+            "--status-file",
+            "--template",
+            "--on-conflict",
+            "--line-style",
+            "--static",
+            "--source",
+            "--mirrored",
+            "-l",
+            "--listen",
+            "-u",
+            "--upstream",
+            "--limit",
+        ];
+        let flags = ["-4", "-6", "-a", "--all"];
         let mut iter = args.into_iter().peekable();
 
+        match iter.peek().map(|a| &a[..]) {
+            Some("ip") => cli.command = Command::Ip,
+            Some("windows-host") => cli.command = Command::WindowsHost,
+            Some("dns") => cli.command = Command::Dns,
+            Some("remove") => cli.command = Command::Remove,
+            Some("history") => cli.command = Command::History,
+            Some("status") => cli.command = Command::Status,
+            Some("wslconfig") => cli.command = Command::WslConfig,
+            _ => (),
+        };
+
+        if cli.command != Command::Write {
+            iter.next();
+        }
+
         while let Some(text) = iter.next() {
-            if options.contains(&&text[..]) {
+            if flags.contains(&&text[..]) {
+                cli.flag(&text);
+            } else if options.contains(&&text[..]) {
                 match iter.peek() {
                     Some(value) if !options.contains(&&value[..]) => {
                         cli.apply(&text, Some(value.to_owned()));
@@ -84,16 +401,224 @@ Options:
                     Some(_) => (),
                     None => cli.apply(&text, None),
                 };
+            } else {
+                cli.words.push(text);
             }
         }
 
-        if cli.names.is_empty() {
+        if cli.names.is_empty() && cli.command == Command::WindowsHost {
+            cli.names.push(lib::DEFAULT_WINDOWS_HOST.to_owned());
+        } else if cli.names.is_empty() && (cli.config.is_none() || !cli.writes()) {
             cli.names.push(lib::DEFAULT_HOST.to_owned());
         }
 
         cli
     }
 
+    fn print_ips(app: &App) -> Result<(), String> {
+        let mut discovery = app.discovery();
+
+        if false == app.all {
+            let ips = discovery.find(&app.distro)?;
+            if ips.is_empty() {
+                return Err(format!("No address found on {}.", app.query.interface));
+            }
+
+            ips.iter().for_each(|ip| println!("{}", ip));
+            return Ok(());
+        }
+
+        let distros: Vec<Option<String>> = lib::find_wsl_distros()?
+            .iter()
+            .map(|d| lib::distro_name(d))
+            .filter(|d| !d.is_empty())
+            .map(Some)
+            .collect();
+
+        // booting every installed distro just to list addresses is rarely wanted
+        if app.on_stopped.is_none() {
+            discovery.on_stopped = lib::OnStopped::Fail;
+        }
+
+        for found in discovery.find_all(&distros) {
+            let distro = found.distro.unwrap_or_default();
+            match found.result {
+                Ok(f) => f.ips.iter().for_each(|ip| println!("{} {}", distro, ip)),
+                Err(e) => eprintln!("{}", e),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn write_windows_host(app: &App) -> Result<(), String> {
+        let discovery = app.discovery();
+        let ip = lib::find_windows_host_ip(&discovery, &app.distro)?;
+
+        lib::write_distro_hosts(&discovery, &app.distro, &ip, &app.names)?;
+        app.names
+            .iter()
+            .for_each(|name| println!("{} {}", ip, name));
+
+        Ok(())
+    }
+
+    fn serve_dns(app: &App) -> Result<(), String> {
+        use std::sync::Arc;
+
+        let parse = |text: &str| {
+            text.parse::<std::net::SocketAddr>()
+                .map_err(|e| format!("Invalid address {}: {}", text, e))
+        };
+
+        let mut config = lib::dns::DnsConfig::new(app.names.clone());
+        config.listen = parse(app.listen.as_deref().unwrap_or(lib::dns::DEFAULT_LISTEN))?;
+        config.upstream = match &app.upstream {
+            Some(u) => Some(parse(u)?),
+            None => None,
+        };
+
+        let discovery = app.discovery();
+        let max_age = std::time::Duration::from_secs(config.ttl as u64);
+        let lookup = lib::dns::DiscoveryLookup::new(discovery.clone(), app.distro.clone(), max_age);
+        let server = lib::dns::DnsServer::bind(config, Arc::new(lookup))?;
+
+        println!("Listening on {}", server.local_addr()?);
+        server.serve(&discovery.cancel)
+    }
+
+    fn print_history(app: &App) -> Result<(), String> {
+        let query = lib::journal::Query {
+            distro: app.distro.clone(),
+            limit: Some(app.limit),
+            ..lib::journal::Query::default()
+        };
+
+        lib::Journal::open_default()?
+            .query(&query)?
+            .iter()
+            .for_each(|entry| println!("{}", entry));
+
+        Ok(())
+    }
+
+    fn wslconfig(app: &App) -> Result<(), String> {
+        let path = lib::wslconfig::path()?;
+        let mut loaded = lib::wslconfig::Loaded::read(&path)?;
+        let words: Vec<&str> = app.words.iter().map(|w| &w[..]).collect();
+
+        match words[..] {
+            [] | ["get"] => {
+                for (section, key, value) in loaded.config.entries() {
+                    println!("{}.{}={}", section, key, value);
+                }
+                return Ok(());
+            }
+            ["get", setting] => {
+                let (section, key) = lib::wslconfig::split_key(setting)?;
+                return match loaded.config.get(&section, &key) {
+                    Some(value) => {
+                        println!("{}", value);
+                        Ok(())
+                    }
+                    None => Err(format!("{} is not set.", setting)),
+                };
+            }
+            ["set", setting, value] => {
+                let (section, key) = lib::wslconfig::split_key(setting)?;
+                loaded.config.set(&section, &key, value)?;
+            }
+            ["unset", setting] => {
+                let (section, key) = lib::wslconfig::split_key(setting)?;
+                if false == loaded.config.unset(&section, &key) {
+                    return Err(format!("{} is not set.", setting));
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Usage: wsl2-ip-host wslconfig {}",
+                    "[get [<setting>] | set <setting> <value> | unset <setting>]"
+                ))
+            }
+        };
+
+        if loaded.save()? {
+            println!(
+                "Saved {}, run `wsl --shutdown` for WSL to pick up the change.",
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn print_status(cfg: &lib::Config) -> Result<(), String> {
+        let status = cfg.status()?;
+
+        println!("hosts file: {}", cfg.hosts_path);
+        match status.managed.is_empty() {
+            true => println!("managed: none"),
+            false => status
+                .managed
+                .iter()
+                .for_each(|line| println!("managed: {}", line)),
+        };
+        status
+            .blocks
+            .iter()
+            .for_each(|block| println!("left alone: {}", block));
+        status
+            .conflicts
+            .iter()
+            .for_each(|conflict| println!("conflict: {}", conflict));
+
+        Ok(())
+    }
+
+    /// the saved settings, if any, with the command line on top
+    fn config(app: &App) -> Result<lib::Config, String> {
+        let mut cfg = match &app.config {
+            Some(path) => lib::settings::read(std::path::Path::new(path))?,
+            None => lib::Config::new(),
+        };
+        if false == app.names.is_empty() {
+            cfg.set_names(app.names.clone());
+        }
+        if false == app.subdomains.is_empty() {
+            cfg.subdomains = app.subdomains.clone();
+        }
+        cfg.port_forwards.extend(app.forwards.iter().cloned());
+        if false == app.ssh_hosts.is_empty() {
+            let ssh = cfg.ssh.get_or_insert(lib::SshConfig {
+                path: None,
+                hosts: vec![],
+            });
+            ssh.hosts
+                .extend(app.ssh_hosts.iter().map(|alias| lib::SshHost::new(alias)));
+        }
+        cfg.outputs.extend(app.outputs.iter().cloned());
+        if let Some(policy) = app.on_conflict {
+            cfg.on_conflict = policy;
+        }
+        if let Some(style) = app.line_style {
+            cfg.line_style = style;
+        }
+        cfg.static_entries
+            .extend(app.static_entries.iter().cloned());
+        cfg.sources.extend(app.sources.iter().cloned());
+        if let Some(mirrored) = app.mirrored {
+            cfg.mirrored = mirrored;
+        }
+        if let Some(on_stopped) = app.on_stopped {
+            cfg.on_stopped = on_stopped;
+        }
+        if app.distro.is_some() {
+            cfg.distro = app.distro.clone();
+        }
+
+        Ok(cfg)
+    }
+
     pub fn run() -> Result<(), String> {
         let app = parse_args();
 
@@ -102,10 +627,48 @@ Options:
             return Ok(());
         }
 
-        let mut cfg = lib::Config::new();
-        cfg.set_names(app.names.clone());
-        cfg.distro = app.distro.clone();
-        let ip = find_wsl_ip(&cfg.distro)?;
-        lib::write_changes(&ip, &cfg)
+        if false == app.errors.is_empty() {
+            return Err(app.errors.join("\n"));
+        }
+
+        if let Err(e) = lib::logging::init("wsl2-ip-host-cli", &app.log) {
+            eprintln!("Unable to start logging: {}", e);
+        }
+
+        match app.command {
+            Command::Ip => return print_ips(&app),
+            Command::WindowsHost => return write_windows_host(&app),
+            Command::Dns => return serve_dns(&app),
+            Command::History => return print_history(&app),
+            Command::WslConfig => return wslconfig(&app),
+            Command::Write | Command::Remove | Command::Status => (),
+        };
+
+        let cfg = config(&app)?;
+        if app.command == Command::Status {
+            return print_status(&cfg);
+        }
+        if app.command == Command::Remove {
+            return lib::remove_targets(&cfg, &lib::Cancel::new());
+        }
+
+        let mut discovery = match app.strategies.is_empty() {
+            true => app.discovery().for_config(&cfg),
+            false => app.discovery(),
+        };
+        discovery.on_stopped = cfg.on_stopped;
+        let journal = match lib::Journal::open_default() {
+            Ok(journal) => Some(journal),
+            Err(e) => {
+                eprintln!("Not keeping a journal: {}", e);
+                None
+            }
+        };
+        lib::sync(&discovery, &cfg, journal.as_ref())?
+            .warnings()
+            .iter()
+            .for_each(|warning| eprintln!("{}", warning));
+
+        Ok(())
     }
 }
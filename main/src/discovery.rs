@@ -0,0 +1,280 @@
+use crate::distro::{self, Distro, OnStopped, Phase, State};
+use crate::{util, Config, IpQuery, Strategy};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// how long a single `wsl.exe` call may take, long enough for a cold distro to boot
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// how long the distros' states from `wsl -l -v` are reused for
+pub const STATES_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// shared flag for abandoning discovery that is still running, clones share the flag
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Cancel {
+        Cancel::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// `wsl -l -v` and when it was run
+type States = Option<(Instant, Vec<Distro>)>;
+
+/// ip lookups against one or many distros with a timeout per `wsl.exe` call
+#[derive(Clone, Debug)]
+pub struct Discovery {
+    pub query: IpQuery,
+    pub timeout: Duration,
+    pub cancel: Cancel,
+    /// tried in order, the first one to report an address wins
+    pub strategies: Vec<Strategy>,
+    /// whether a stopped distro is started or reported
+    pub on_stopped: OnStopped,
+    /// how long a stopped distro gets to boot and bring up the interface
+    pub boot_timeout: Duration,
+    /// the last `wsl -l -v`, shared by clones so lookups in a row don't each run it
+    states: Arc<Mutex<States>>,
+}
+
+/// the addresses a distro reported along with the strategy that found them
+#[derive(Clone, Debug)]
+pub struct Found {
+    pub strategy: Strategy,
+    pub ips: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct DistroResult {
+    pub distro: Option<String>,
+    pub result: Result<Found, String>,
+}
+
+impl Default for Discovery {
+    fn default() -> Discovery {
+        Discovery::new()
+    }
+}
+
+impl Discovery {
+    pub fn new() -> Discovery {
+        Discovery {
+            query: IpQuery::new(),
+            timeout: DEFAULT_TIMEOUT,
+            cancel: Cancel::new(),
+            strategies: Strategy::ALL.to_vec(),
+            on_stopped: OnStopped::default(),
+            boot_timeout: distro::DEFAULT_BOOT_TIMEOUT,
+            states: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// a copy sharing the cancel flag that uses the strategies chosen in `config` and
+    /// deals with a stopped distro as it says
+    pub fn for_config(&self, config: &Config) -> Discovery {
+        let mut discovery = self.clone();
+        discovery.strategies = config.strategies.clone();
+        discovery.on_stopped = config.on_stopped;
+        discovery
+    }
+
+    /// all addresses of the queried interface in a single distro
+    pub fn find(&self, distro: &Option<String>) -> Result<Vec<String>, String> {
+        self.find_with_strategy(distro).map(|found| found.ips)
+    }
+
+    /// walks `strategies` until one reports an address. a stopped distro is started
+    /// first, as `on_stopped` says, and given until `boot_timeout` to get an address.
+    /// the error names the phase that failed
+    pub fn find_with_strategy(&self, distro: &Option<String>) -> Result<Found, String> {
+        match self.start_if_stopped(distro)? {
+            true => self.wait_for_address(distro),
+            false => self.discover(distro, None),
+        }
+    }
+
+    /// `wsl -l -v`, reused for `STATES_MAX_AGE`
+    fn states(&self) -> Result<Vec<Distro>, String> {
+        let mut states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((at, list)) = states.as_ref() {
+            if at.elapsed() < STATES_MAX_AGE {
+                return Ok(list.to_owned());
+            }
+        }
+
+        let list = distro::list(self.timeout, &self.cancel)?;
+        *states = Some((Instant::now(), list.to_owned()));
+        Ok(list)
+    }
+
+    /// whether `distro` was stopped and had to be started. when `wsl -l -v` can't be
+    /// run, as on builds too old for `-v`, the distro is left for `wsl.exe` to boot
+    fn start_if_stopped(&self, distro: &Option<String>) -> Result<bool, String> {
+        let failed =
+            |phase: Phase, e: &str| format!("{}: Unable to {}: {}", label(distro), phase, e);
+
+        let list = match self.states() {
+            Ok(list) => list,
+            Err(e) if self.cancel.is_cancelled() => return Err(failed(Phase::Check, &e)),
+            Err(e) => {
+                log::warn!("{}: Unable to {}: {}", label(distro), Phase::Check, e);
+                return Ok(false);
+            }
+        };
+
+        match distro::find(&list, distro).map(|d| &d.state) {
+            None => Err(failed(Phase::Check, "It is not installed.")),
+            Some(State::Stopped) if self.on_stopped == OnStopped::Fail => Err(format!(
+                "{}: The distro is stopped and on-stopped is {}.",
+                label(distro),
+                self.on_stopped
+            )),
+            Some(State::Stopped) => {
+                log::info!("starting {}", label(distro));
+                // whichever way it goes the recorded state is out of date
+                *self.states.lock().unwrap_or_else(|e| e.into_inner()) = None;
+                distro::start(distro, self.boot_timeout, &self.cancel)
+                    .map_err(|e| failed(Phase::Start, &e))?;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+        }
+    }
+
+    /// `discover` until the freshly started distro's interface has an address, giving up
+    /// at `boot_timeout`
+    fn wait_for_address(&self, distro: &Option<String>) -> Result<Found, String> {
+        let start = Instant::now();
+        let deadline = start + self.boot_timeout;
+
+        loop {
+            let e = match self.discover(distro, Some(deadline)) {
+                Ok(found) => return Ok(found),
+                Err(e) => e,
+            };
+
+            if self.cancel.is_cancelled() || start.elapsed() >= self.boot_timeout {
+                return Err(format!(
+                    "{}: Unable to {}: {} had none after {}s. {}",
+                    label(distro),
+                    Phase::Address,
+                    self.query.interface,
+                    start.elapsed().as_secs(),
+                    e
+                ));
+            }
+
+            let left = deadline.saturating_duration_since(Instant::now());
+            std::thread::sleep(left.min(Duration::from_millis(500)));
+        }
+    }
+
+    /// walks `strategies` until one reports an address, no call outlasting `deadline`.
+    /// a timeout or cancel ends the walk straight away since the distro itself is the
+    /// problem, not the tool
+    fn discover(
+        &self,
+        distro: &Option<String>,
+        deadline: Option<Instant>,
+    ) -> Result<Found, String> {
+        let mut failures = vec![];
+
+        for strategy in self.strategies.iter() {
+            let timeout = match deadline {
+                Some(d) => self
+                    .timeout
+                    .min(d.saturating_duration_since(Instant::now())),
+                None => self.timeout,
+            };
+            let cmd = util::wsl_cmd(distro, &strategy.args(&self.query));
+            let output = match util::run_with_timeout(cmd, timeout, &self.cancel) {
+                Ok(output) => output,
+                Err(e @ util::RunError::Io(_)) => {
+                    failures.push(format!("{}: {}", strategy, e));
+                    continue;
+                }
+                Err(e) => return Err(format!("{}: {}", label(distro), e)),
+            };
+
+            if false == output.status.success() {
+                failures.push(format!("{}: command failed", strategy));
+                continue;
+            }
+
+            let txt = String::from_utf8_lossy(&output.stdout);
+            let ips = strategy.parse(&txt, &self.query);
+
+            if ips.is_empty() {
+                failures.push(format!("{}: no address in output", strategy));
+            } else {
+                log::debug!("{} found {:?} with {}", label(distro), ips, strategy);
+                return Ok(Found {
+                    strategy: *strategy,
+                    ips,
+                });
+            }
+        }
+
+        if failures.is_empty() {
+            return Err(format!(
+                "{}: No discovery strategies configured.",
+                label(distro)
+            ));
+        }
+
+        for failure in failures.iter() {
+            log::debug!("{}: {}", label(distro), failure);
+        }
+        Err(format!(
+            "{}: Unable to find an address ({}).",
+            label(distro),
+            failures.join("; ")
+        ))
+    }
+
+    /// the first address of the queried interface in a single distro
+    pub fn find_ip(&self, distro: &Option<String>) -> Result<String, String> {
+        // a successful strategy always reports at least one address
+        Ok(self.find(distro)?.remove(0))
+    }
+
+    /// queries every distro at once and waits for all of them, a distro that fails or
+    /// times out only affects its own entry. results keep the order of `distros`
+    pub fn find_all(&self, distros: &[Option<String>]) -> Vec<DistroResult> {
+        let handles: Vec<_> = distros
+            .iter()
+            .map(|distro| {
+                let discovery = self.clone();
+                let distro = distro.to_owned();
+                std::thread::spawn(move || discovery.find_with_strategy(&distro))
+            })
+            .collect();
+
+        distros
+            .iter()
+            .zip(handles)
+            .map(|(distro, handle)| DistroResult {
+                distro: distro.to_owned(),
+                result: handle
+                    .join()
+                    .unwrap_or_else(|_| Err("Discovery thread panicked.".to_owned())),
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn label(distro: &Option<String>) -> &str {
+    match distro {
+        Some(d) => d,
+        None => "default distro",
+    }
+}
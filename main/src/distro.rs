@@ -0,0 +1,195 @@
+use crate::{util, Cancel};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// how long starting a stopped distro and waiting for its address may take
+pub const DEFAULT_BOOT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// what `wsl -l -v` says a distro is doing
+#[derive(Clone, Debug, PartialEq)]
+pub enum State {
+    Running,
+    Stopped,
+    /// `Installing`, `Converting`, `Uninstalling` or the state in another language
+    Other(String),
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            State::Running => write!(f, "Running"),
+            State::Stopped => write!(f, "Stopped"),
+            State::Other(state) => write!(f, "{}", state),
+        }
+    }
+}
+
+/// a line of `wsl -l -v`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distro {
+    pub name: String,
+    /// marked with `*`, the one run when no distro is given
+    pub default: bool,
+    pub state: State,
+    pub version: String,
+}
+
+/// what to do when the distro is stopped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnStopped {
+    /// start it and wait for its address
+    #[default]
+    Start,
+    /// fail without starting it
+    Fail,
+}
+
+named_enum!(OnStopped, "stopped behaviour", {
+    Start => "start",
+    Fail => "fail",
+});
+
+/// the steps of getting an address out of a distro, named in the error when one fails
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    /// `wsl -l -v`
+    Check,
+    /// booting a stopped distro
+    Start,
+    /// waiting for the interface to get an address after booting
+    Address,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Check => write!(f, "check the distro's state"),
+            Phase::Start => write!(f, "start the distro"),
+            Phase::Address => write!(f, "get an address after starting the distro"),
+        }
+    }
+}
+
+/// wsl.exe writes its own messages in utf-16
+pub fn decode(bytes: &[u8]) -> Result<String, String> {
+    let b: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+
+    let text = String::from_utf16(&b).map_err(|e| format!("{}", e))?;
+    Ok(text.trim_start_matches('\u{feff}').to_owned())
+}
+
+/// the distros in `wsl -l -v` output. the name comes first and the version last, so a
+/// state of several words in another language still parses
+pub fn parse_list(text: &str) -> Vec<Distro> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let line = line.trim();
+            let default = line.starts_with('*');
+            let words: Vec<&str> = line.trim_start_matches('*').split_whitespace().collect();
+            if words.len() < 3 {
+                return None;
+            }
+
+            let state = match words[1..words.len() - 1].join(" ") {
+                s if s == "Running" => State::Running,
+                s if s == "Stopped" => State::Stopped,
+                s => State::Other(s),
+            };
+
+            Some(Distro {
+                name: words[0].to_owned(),
+                default,
+                state,
+                version: words[words.len() - 1].to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// `distro` in `list`, the default one when it is `None`
+pub fn find<'a>(list: &'a [Distro], distro: &Option<String>) -> Option<&'a Distro> {
+    match distro {
+        Some(name) => list.iter().find(|d| d.name.eq_ignore_ascii_case(name)),
+        None => list.iter().find(|d| d.default),
+    }
+}
+
+/// runs `wsl.exe -l -v`
+pub fn list(timeout: Duration, cancel: &Cancel) -> Result<Vec<Distro>, String> {
+    let mut cmd = util::hidden_cmd("wsl.exe");
+    cmd.args(["-l", "-v"]);
+
+    let output = util::run_with_timeout(cmd, timeout, cancel).map_err(|e| format!("{}", e))?;
+    if false == output.status.success() {
+        let text = decode(&output.stdout).unwrap_or_default();
+        return Err(match text.trim() {
+            "" => "wsl.exe -l -v failed.".to_owned(),
+            text => text.to_owned(),
+        });
+    }
+
+    Ok(parse_list(&decode(&output.stdout)?))
+}
+
+/// boots `distro` by running `true` in it
+pub fn start(distro: &Option<String>, timeout: Duration, cancel: &Cancel) -> Result<(), String> {
+    let cmd = util::wsl_cmd(distro, &["true".to_owned()]);
+    let output = util::run_with_timeout(cmd, timeout, cancel).map_err(|e| format!("{}", e))?;
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "wsl.exe exited with {}. {}",
+            output.status,
+            decode(&output.stdout).unwrap_or_default().trim()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parses_verbose_list() {
+        let text = "  NAME                   STATE           VERSION\r
+* Ubuntu-22.04           Running         2\r
+  docker-desktop         Stopped         2\r
+  Debian                 Wird ausgeführt 1\r
+";
+        let list = parse_list(&decode(&utf16(text)).unwrap());
+
+        assert_eq!(list.len(), 3);
+        assert_eq!(
+            list[0],
+            Distro {
+                name: "Ubuntu-22.04".to_owned(),
+                default: true,
+                state: State::Running,
+                version: "2".to_owned(),
+            }
+        );
+        assert_eq!(list[1].state, State::Stopped);
+        assert_eq!(list[2].state, State::Other("Wird ausgeführt".to_owned()));
+        assert_eq!(list[2].version, "1");
+
+        assert_eq!(
+            find(&list, &None).map(|d| &d.name[..]),
+            Some("Ubuntu-22.04")
+        );
+        assert_eq!(
+            find(&list, &Some("Docker-Desktop".to_owned())).map(|d| &d.state),
+            Some(&State::Stopped)
+        );
+        assert_eq!(find(&list, &Some("Alpine".to_owned())), None);
+    }
+}
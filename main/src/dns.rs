@@ -0,0 +1,838 @@
+use crate::{names, Cancel, Discovery};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:53";
+pub const DEFAULT_TTL: u32 = 30;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_FORMERR: u8 = 1;
+pub const RCODE_SERVFAIL: u8 = 2;
+pub const RCODE_REFUSED: u8 = 5;
+
+const HEADER_LEN: usize = 12;
+const MAX_UDP: usize = 512;
+/// room for queries and replies larger than `MAX_UDP`, as edns allows
+const MAX_EDNS: usize = 4096;
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(3);
+/// how often the listening loops look at the cancel flag
+const POLL: Duration = Duration::from_millis(100);
+/// udp queries answered at once, with up to `QUEUED` more waiting and the rest dropped
+const WORKERS: usize = 8;
+const QUEUED: usize = 64;
+
+/// where the answers for our names come from
+pub trait Lookup: Send + Sync {
+    fn addresses(&self) -> Result<Vec<IpAddr>, String>;
+}
+
+impl<F> Lookup for F
+where
+    F: Fn() -> Result<Vec<IpAddr>, String> + Send + Sync,
+{
+    fn addresses(&self) -> Result<Vec<IpAddr>, String> {
+        self()
+    }
+}
+
+/// the last addresses found and whether a lookup is under way
+#[derive(Default)]
+struct Cache {
+    found: Option<(Instant, Vec<IpAddr>)>,
+    refreshing: bool,
+}
+
+struct Shared {
+    find: Box<dyn Fn() -> Result<Vec<IpAddr>, String> + Send + Sync>,
+    max_age: Duration,
+    cache: Mutex<Cache>,
+    done: Condvar,
+}
+
+impl Shared {
+    /// runs `find` without holding the lock and stores what it found, a failure keeps
+    /// the addresses found before
+    fn refresh(&self) -> Result<Vec<IpAddr>, String> {
+        let result = (self.find)();
+
+        let mut cache = self.cache.lock().map_err(|_| "Lookup cache poisoned.")?;
+        cache.refreshing = false;
+        if let Ok(ips) = &result {
+            cache.found = Some((Instant::now(), ips.to_owned()));
+        }
+        self.done.notify_all();
+
+        result
+    }
+}
+
+/// the live address of a distro, cached for `max_age` so a burst of queries doesn't
+/// start a `wsl.exe` each. once the cache is stale its addresses are still answered
+/// while a background lookup refreshes them, so a slow or booting distro only holds up
+/// the very first queries
+pub struct DiscoveryLookup {
+    shared: Arc<Shared>,
+}
+
+impl DiscoveryLookup {
+    pub fn new(discovery: Discovery, distro: Option<String>, max_age: Duration) -> DiscoveryLookup {
+        DiscoveryLookup::with(
+            move || {
+                Ok(discovery
+                    .find(&distro)?
+                    .iter()
+                    .filter_map(|ip| ip.parse().ok())
+                    .collect())
+            },
+            max_age,
+        )
+    }
+
+    fn with<F>(find: F, max_age: Duration) -> DiscoveryLookup
+    where
+        F: Fn() -> Result<Vec<IpAddr>, String> + Send + Sync + 'static,
+    {
+        DiscoveryLookup {
+            shared: Arc::new(Shared {
+                find: Box::new(find),
+                max_age,
+                cache: Mutex::new(Cache::default()),
+                done: Condvar::new(),
+            }),
+        }
+    }
+}
+
+impl Lookup for DiscoveryLookup {
+    fn addresses(&self) -> Result<Vec<IpAddr>, String> {
+        let poisoned = |_| "Lookup cache poisoned.".to_owned();
+        let mut cache = self.shared.cache.lock().map_err(poisoned)?;
+
+        // nothing to answer with yet, wait for a lookup someone else started
+        while cache.found.is_none() && cache.refreshing {
+            cache = self.shared.done.wait(cache).map_err(poisoned)?;
+        }
+
+        let stale = match &cache.found {
+            Some((at, ips)) if at.elapsed() < self.shared.max_age => return Ok(ips.to_owned()),
+            Some((_, ips)) => Some(ips.to_owned()),
+            None => None,
+        };
+        let refresh = false == cache.refreshing;
+        cache.refreshing = true;
+        drop(cache);
+
+        match stale {
+            Some(ips) => {
+                if refresh {
+                    let shared = self.shared.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = shared.refresh() {
+                            log::warn!("Unable to refresh the address: {}", e);
+                        }
+                    });
+                }
+                Ok(ips)
+            }
+            None => self.shared.refresh(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DnsConfig {
+    pub listen: SocketAddr,
+    /// names answered with the live address, compared without case. a `*.domain`
+    /// entry answers for every name below `domain`
+    pub names: Vec<String>,
+    /// where every other query goes, they are refused when this is `None`
+    pub upstream: Option<SocketAddr>,
+    pub ttl: u32,
+}
+
+impl DnsConfig {
+    pub fn new(names: Vec<String>) -> DnsConfig {
+        DnsConfig {
+            listen: DEFAULT_LISTEN.parse().unwrap(),
+            names,
+            upstream: None,
+            ttl: DEFAULT_TTL,
+        }
+    }
+
+    fn is_ours(&self, name: &str) -> bool {
+        self.names.iter().any(|n| names::matches(n, name))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// the parts of a request the responder needs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    pub id: u16,
+    pub flags: u16,
+    pub question: Question,
+    /// offset just past the question, it's echoed back verbatim in the answer
+    end: usize,
+}
+
+/// the parts of a reply a client needs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub id: u16,
+    pub rcode: u8,
+    pub answers: Vec<IpAddr>,
+}
+
+fn read_u16(buf: &[u8], at: usize) -> Result<u16, String> {
+    match buf.get(at..at + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err("Truncated message.".to_owned()),
+    }
+}
+
+/// reads the name at `at`, following compression pointers. returns the name and the
+/// offset after it in the original position
+fn read_name(buf: &[u8], mut at: usize) -> Result<(String, usize), String> {
+    let mut labels: Vec<String> = vec![];
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *buf.get(at).ok_or("Truncated name.")? as usize;
+
+        if len & 0xC0 == 0xC0 {
+            let pointer = (read_u16(buf, at)? & 0x3FFF) as usize;
+            end = end.or(Some(at + 2));
+            jumps += 1;
+            if jumps > 16 {
+                return Err("Name compression loop.".to_owned());
+            }
+            at = pointer;
+        } else if len == 0 {
+            let end = end.unwrap_or(at + 1);
+            return Ok((labels.join("."), end));
+        } else {
+            let label = buf.get(at + 1..at + 1 + len).ok_or("Truncated label.")?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            at += 1 + len;
+        }
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+pub fn parse_query(buf: &[u8]) -> Result<Query, String> {
+    let id = read_u16(buf, 0)?;
+    let flags = read_u16(buf, 2)?;
+
+    if read_u16(buf, 4)? != 1 {
+        return Err("Expected exactly one question.".to_owned());
+    }
+
+    let (name, at) = read_name(buf, HEADER_LEN)?;
+    let question = Question {
+        name,
+        qtype: read_u16(buf, at)?,
+        qclass: read_u16(buf, at + 2)?,
+    };
+
+    Ok(Query {
+        id,
+        flags,
+        question,
+        end: at + 4,
+    })
+}
+
+/// a reply to `query` carrying `answers` of the queried type
+pub fn build_response(
+    query: &Query,
+    raw: &[u8],
+    rcode: u8,
+    answers: &[IpAddr],
+    ttl: u32,
+) -> Vec<u8> {
+    // QR and AA set, opcode and RD copied from the query, RA clear
+    let flags = 0x8000 | 0x0400 | (query.flags & 0x7900) | rcode as u16;
+
+    let mut out = vec![];
+    out.extend_from_slice(&query.id.to_be_bytes());
+    out.extend_from_slice(&flags.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    out.extend_from_slice(&raw[HEADER_LEN..query.end]);
+
+    for ip in answers {
+        // pointer to the question's name right after the header
+        out.extend_from_slice(&[0xC0, HEADER_LEN as u8]);
+        let (rtype, data) = match ip {
+            IpAddr::V4(v4) => (TYPE_A, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (TYPE_AAAA, v6.octets().to_vec()),
+        };
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&ttl.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+
+    out
+}
+
+/// a header-only reply for requests too broken to echo a question
+fn error_response(raw: &[u8], rcode: u8) -> Vec<u8> {
+    let id = read_u16(raw, 0).unwrap_or(0);
+    let flags = 0x8000 | rcode as u16;
+
+    let mut out = vec![];
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&flags.to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+    out
+}
+
+pub fn build_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut out = vec![];
+    out.extend_from_slice(&id.to_be_bytes());
+    // standard query with recursion desired
+    out.extend_from_slice(&0x0100u16.to_be_bytes());
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    write_name(&mut out, name);
+    out.extend_from_slice(&qtype.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out
+}
+
+pub fn parse_response(buf: &[u8]) -> Result<Response, String> {
+    let id = read_u16(buf, 0)?;
+    let rcode = (read_u16(buf, 2)? & 0x000F) as u8;
+    let qdcount = read_u16(buf, 4)?;
+    let ancount = read_u16(buf, 6)?;
+
+    let mut at = HEADER_LEN;
+    for _ in 0..qdcount {
+        at = read_name(buf, at)?.1 + 4;
+    }
+
+    let mut answers = vec![];
+    for _ in 0..ancount {
+        at = read_name(buf, at)?.1;
+        let rtype = read_u16(buf, at)?;
+        let len = read_u16(buf, at + 8)? as usize;
+        let data = buf.get(at + 10..at + 10 + len).ok_or("Truncated record.")?;
+
+        match (rtype, len) {
+            (TYPE_A, 4) => answers.push(IpAddr::from([data[0], data[1], data[2], data[3]])),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                answers.push(IpAddr::from(octets));
+            }
+            _ => (),
+        };
+
+        at += 10 + len;
+    }
+
+    Ok(Response { id, rcode, answers })
+}
+
+/// sends one query over udp and waits for the reply
+pub fn resolve(server: SocketAddr, name: &str, qtype: u16) -> Result<Response, String> {
+    let socket = UdpSocket::bind(local_any(server)).map_err(|e| format!("{}", e))?;
+    socket
+        .set_read_timeout(Some(FORWARD_TIMEOUT))
+        .map_err(|e| format!("{}", e))?;
+
+    let id = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or(0);
+    socket
+        .send_to(&build_query(id, name, qtype), server)
+        .map_err(|e| format!("{}", e))?;
+
+    let mut buf = [0u8; MAX_UDP];
+    let (len, _) = socket.recv_from(&mut buf).map_err(|e| format!("{}", e))?;
+
+    parse_response(&buf[..len])
+}
+
+fn local_any(peer: SocketAddr) -> SocketAddr {
+    match peer {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+    }
+}
+
+fn forward(upstream: SocketAddr, raw: &[u8]) -> Result<Vec<u8>, String> {
+    let socket = UdpSocket::bind(local_any(upstream)).map_err(|e| format!("{}", e))?;
+    socket
+        .set_read_timeout(Some(FORWARD_TIMEOUT))
+        .map_err(|e| format!("{}", e))?;
+    socket
+        .send_to(raw, upstream)
+        .map_err(|e| format!("{}", e))?;
+
+    let mut buf = vec![0u8; MAX_EDNS];
+    let (len, _) = socket.recv_from(&mut buf).map_err(|e| format!("{}", e))?;
+    buf.truncate(len);
+
+    Ok(buf)
+}
+
+/// answers a single request, shared by the udp and tcp listeners
+pub fn respond(config: &DnsConfig, lookup: &dyn Lookup, raw: &[u8]) -> Vec<u8> {
+    let query = match parse_query(raw) {
+        Ok(q) => q,
+        Err(_) => return error_response(raw, RCODE_FORMERR),
+    };
+
+    if false == config.is_ours(&query.question.name) {
+        return match config.upstream {
+            Some(upstream) => forward(upstream, raw)
+                .unwrap_or_else(|_| build_response(&query, raw, RCODE_SERVFAIL, &[], 0)),
+            None => build_response(&query, raw, RCODE_REFUSED, &[], 0),
+        };
+    }
+
+    let ips = match lookup.addresses() {
+        Ok(ips) => ips,
+        Err(_) => return build_response(&query, raw, RCODE_SERVFAIL, &[], 0),
+    };
+
+    // other record types for our names get an empty answer rather than an error
+    let answers: Vec<IpAddr> = ips
+        .into_iter()
+        .filter(|ip| {
+            matches!(
+                (query.question.qtype, ip),
+                (TYPE_A, IpAddr::V4(_)) | (TYPE_AAAA, IpAddr::V6(_))
+            )
+        })
+        .collect();
+
+    build_response(&query, raw, RCODE_NOERROR, &answers, config.ttl)
+}
+
+/// udp and tcp listeners on the same address answering for the configured names
+pub struct DnsServer {
+    config: DnsConfig,
+    lookup: Arc<dyn Lookup>,
+    udp: UdpSocket,
+    tcp: TcpListener,
+}
+
+impl DnsServer {
+    pub fn bind(config: DnsConfig, lookup: Arc<dyn Lookup>) -> Result<DnsServer, String> {
+        let udp = UdpSocket::bind(config.listen).map_err(|e| format!("{}", e))?;
+        // port 0 picks a free udp port, tcp has to follow it
+        let addr = udp.local_addr().map_err(|e| format!("{}", e))?;
+        let tcp = TcpListener::bind(addr).map_err(|e| format!("{}", e))?;
+
+        udp.set_read_timeout(Some(POLL))
+            .map_err(|e| format!("{}", e))?;
+        tcp.set_nonblocking(true).map_err(|e| format!("{}", e))?;
+
+        Ok(DnsServer {
+            config,
+            lookup,
+            udp,
+            tcp,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.udp.local_addr().map_err(|e| format!("{}", e))
+    }
+
+    /// answers queries until `cancel` is set, each on a thread of its own so a slow
+    /// lookup or forward doesn't hold up the others
+    pub fn serve(self, cancel: &Cancel) -> Result<(), String> {
+        let tcp = {
+            let config = self.config.clone();
+            let lookup = self.lookup.clone();
+            let listener = self.tcp.try_clone().map_err(|e| format!("{}", e))?;
+            let cancel = cancel.clone();
+            std::thread::spawn(move || serve_tcp(&config, lookup, listener, &cancel))
+        };
+
+        let udp = Arc::new(self.udp);
+        let (queue, queued) = mpsc::sync_channel::<(Vec<u8>, SocketAddr)>(QUEUED);
+        let queued = Arc::new(Mutex::new(queued));
+        for _ in 0..WORKERS {
+            let config = self.config.clone();
+            let lookup = self.lookup.clone();
+            let udp = udp.clone();
+            let queued = queued.clone();
+            // ends once `queue` is dropped
+            std::thread::spawn(move || loop {
+                let next = queued.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                let (raw, peer) = match next {
+                    Ok(query) => query,
+                    Err(_) => return,
+                };
+                let reply = respond(&config, &*lookup, &raw);
+                udp.send_to(&reply, peer).ok();
+            });
+        }
+
+        let mut buf = [0u8; MAX_EDNS];
+        while false == cancel.is_cancelled() {
+            let (len, peer) = match udp.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(e) if is_timeout(&e) => continue,
+                // windows reports an earlier reply that found no one listening this way
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    log::warn!("Unable to receive a dns query: {}", e);
+                    std::thread::sleep(POLL);
+                    continue;
+                }
+            };
+
+            // answering a reply could bounce packets between us and its sender for ever
+            if is_response(&buf[..len]) {
+                continue;
+            }
+
+            if queue.try_send((buf[..len].to_vec(), peer)).is_err() {
+                log::debug!("dropped a dns query from {}, too many are waiting", peer);
+            }
+        }
+
+        drop(queue);
+        tcp.join().ok();
+        Ok(())
+    }
+}
+
+/// the QR bit of the header flags, set on replies
+fn is_response(raw: &[u8]) -> bool {
+    raw.len() > 2 && raw[2] & 0x80 != 0
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut
+}
+
+fn serve_tcp(config: &DnsConfig, lookup: Arc<dyn Lookup>, listener: TcpListener, cancel: &Cancel) {
+    while false == cancel.is_cancelled() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let config = config.clone();
+                let lookup = lookup.clone();
+                std::thread::spawn(move || handle_tcp(&config, &*lookup, stream));
+            }
+            Err(e) if is_timeout(&e) => std::thread::sleep(POLL),
+            Err(_) => std::thread::sleep(POLL),
+        };
+    }
+}
+
+/// tcp messages carry a two byte length prefix, the connection may hold several
+fn handle_tcp(config: &DnsConfig, lookup: &dyn Lookup, mut stream: TcpStream) {
+    stream.set_nonblocking(false).ok();
+    stream.set_read_timeout(Some(FORWARD_TIMEOUT)).ok();
+
+    loop {
+        let mut len = [0u8; 2];
+        if stream.read_exact(&mut len).is_err() {
+            return;
+        }
+
+        let mut raw = vec![0u8; u16::from_be_bytes(len) as usize];
+        if stream.read_exact(&mut raw).is_err() {
+            return;
+        }
+
+        let reply = respond(config, lookup, &raw);
+        let mut out = (reply.len() as u16).to_be_bytes().to_vec();
+        out.extend_from_slice(&reply);
+
+        if stream.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(config: DnsConfig) -> (SocketAddr, Cancel) {
+        let lookup: Arc<dyn Lookup> = Arc::new(|| {
+            Ok(vec![
+                "172.20.118.42".parse::<IpAddr>().unwrap(),
+                "fd00::5".parse::<IpAddr>().unwrap(),
+            ])
+        });
+
+        let server = DnsServer::bind(config, lookup).unwrap();
+        let addr = server.local_addr().unwrap();
+        let cancel = Cancel::new();
+        let stop = cancel.clone();
+        std::thread::spawn(move || server.serve(&stop));
+
+        (addr, cancel)
+    }
+
+    fn config(upstream: Option<SocketAddr>) -> DnsConfig {
+        let mut config = DnsConfig::new(vec![
+            "host.wsl.internal".to_owned(),
+            "*.myapp.wsl.internal".to_owned(),
+        ]);
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.upstream = upstream;
+        config
+    }
+
+    #[test]
+    fn round_trips_query() {
+        let raw = build_query(7, "Host.WSL.internal.", TYPE_A);
+        let query = parse_query(&raw).unwrap();
+
+        assert_eq!(query.id, 7);
+        assert_eq!(
+            query.question,
+            Question {
+                name: "Host.WSL.internal".to_owned(),
+                qtype: TYPE_A,
+                qclass: CLASS_IN,
+            }
+        );
+    }
+
+    #[test]
+    fn answers_a_and_aaaa_for_our_names() {
+        let (addr, cancel) = start(config(None));
+
+        let a = resolve(addr, "HOST.wsl.internal", TYPE_A).unwrap();
+        assert_eq!(a.rcode, RCODE_NOERROR);
+        assert_eq!(a.answers, vec!["172.20.118.42".parse::<IpAddr>().unwrap()]);
+
+        let aaaa = resolve(addr, "host.wsl.internal", TYPE_AAAA).unwrap();
+        assert_eq!(aaaa.answers, vec!["fd00::5".parse::<IpAddr>().unwrap()]);
+
+        // any other type is an empty answer, not an error
+        let mx = resolve(addr, "host.wsl.internal", 15).unwrap();
+        assert_eq!(mx.rcode, RCODE_NOERROR);
+        assert!(mx.answers.is_empty());
+
+        cancel.cancel();
+    }
+
+    #[test]
+    fn answers_below_wildcard_names() {
+        let (addr, cancel) = start(config(None));
+
+        let reply = resolve(addr, "api.myapp.wsl.internal", TYPE_A).unwrap();
+        assert_eq!(reply.rcode, RCODE_NOERROR);
+        assert_eq!(
+            reply.answers,
+            vec!["172.20.118.42".parse::<IpAddr>().unwrap()]
+        );
+
+        let reply = resolve(addr, "myapp.wsl.internal", TYPE_A).unwrap();
+        assert_eq!(reply.rcode, RCODE_REFUSED);
+
+        cancel.cancel();
+    }
+
+    #[test]
+    fn refuses_other_names_without_upstream() {
+        let (addr, cancel) = start(config(None));
+
+        let reply = resolve(addr, "example.com", TYPE_A).unwrap();
+        assert_eq!(reply.rcode, RCODE_REFUSED);
+        assert!(reply.answers.is_empty());
+
+        cancel.cancel();
+    }
+
+    #[test]
+    fn forwards_other_names_to_upstream() {
+        let mut upstream = config(None);
+        upstream.names = vec!["example.com".to_owned()];
+        let (upstream, stop_upstream) = start(upstream);
+        let (addr, cancel) = start(config(Some(upstream)));
+
+        let reply = resolve(addr, "example.com", TYPE_A).unwrap();
+        assert_eq!(reply.rcode, RCODE_NOERROR);
+        assert_eq!(
+            reply.answers,
+            vec!["172.20.118.42".parse::<IpAddr>().unwrap()]
+        );
+
+        cancel.cancel();
+        stop_upstream.cancel();
+    }
+
+    #[test]
+    fn answers_over_tcp() {
+        let (addr, cancel) = start(config(None));
+
+        let query = build_query(42, "host.wsl.internal", TYPE_A);
+        let mut out = (query.len() as u16).to_be_bytes().to_vec();
+        out.extend_from_slice(&query);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&out).unwrap();
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).unwrap();
+        let mut reply = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut reply).unwrap();
+
+        let reply = parse_response(&reply).unwrap();
+        assert_eq!(reply.id, 42);
+        assert_eq!(
+            reply.answers,
+            vec!["172.20.118.42".parse::<IpAddr>().unwrap()]
+        );
+
+        cancel.cancel();
+    }
+
+    #[test]
+    fn slow_lookups_hold_up_no_other_query() {
+        let lookup: Arc<dyn Lookup> = Arc::new(|| {
+            std::thread::sleep(Duration::from_secs(2));
+            Ok(vec!["172.20.118.42".parse::<IpAddr>().unwrap()])
+        });
+        let server = DnsServer::bind(config(None), lookup).unwrap();
+        let addr = server.local_addr().unwrap();
+        let cancel = Cancel::new();
+        let stop = cancel.clone();
+        std::thread::spawn(move || server.serve(&stop));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .send_to(&build_query(1, "host.wsl.internal", TYPE_A), addr)
+            .unwrap();
+        socket
+            .send_to(&build_query(2, "example.com", TYPE_A), addr)
+            .unwrap();
+
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut buf = [0u8; MAX_UDP];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        let reply = parse_response(&buf[..len]).unwrap();
+        assert_eq!((reply.id, reply.rcode), (2, RCODE_REFUSED));
+
+        cancel.cancel();
+    }
+
+    #[test]
+    fn stale_addresses_are_served_while_refreshing() {
+        let calls = Arc::new(Mutex::new(0));
+        let counted = calls.clone();
+        let lookup = DiscoveryLookup::with(
+            move || {
+                let mut calls = counted.lock().unwrap();
+                *calls += 1;
+                if *calls > 1 {
+                    std::thread::sleep(Duration::from_millis(200));
+                }
+                Ok(vec![format!("172.20.0.{}", calls).parse().unwrap()])
+            },
+            Duration::from_millis(0),
+        );
+        let ip = |s: &str| vec![s.parse::<IpAddr>().unwrap()];
+
+        assert_eq!(lookup.addresses(), Ok(ip("172.20.0.1")));
+        let start = Instant::now();
+        assert_eq!(lookup.addresses(), Ok(ip("172.20.0.1")));
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(lookup.addresses(), Ok(ip("172.20.0.2")));
+    }
+
+    #[test]
+    fn answers_queries_larger_than_512_bytes() {
+        let (addr, cancel) = start(config(None));
+
+        // an edns OPT record padded past the classic udp limit
+        let mut query = build_query(5, "host.wsl.internal", TYPE_A);
+        query[11] = 1;
+        query.extend_from_slice(&[0, 0, 41, 16, 0, 0, 0, 0, 0, 2, 92, 0, 12, 2, 88]);
+        query.extend(vec![0; 600]);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket.send_to(&query, addr).unwrap();
+
+        let mut buf = [0u8; MAX_EDNS];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        let reply = parse_response(&buf[..len]).unwrap();
+        assert_eq!(reply.id, 5);
+        assert_eq!(
+            reply.answers,
+            vec!["172.20.118.42".parse::<IpAddr>().unwrap()]
+        );
+
+        cancel.cancel();
+    }
+
+    #[test]
+    fn ignores_replies() {
+        let (addr, cancel) = start(config(None));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut reply = build_query(3, "host.wsl.internal", TYPE_A);
+        reply[2] |= 0x80;
+        socket.send_to(&reply, addr).unwrap();
+        socket
+            .send_to(&build_query(4, "host.wsl.internal", TYPE_A), addr)
+            .unwrap();
+
+        let mut buf = [0u8; MAX_UDP];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(parse_response(&buf[..len]).unwrap().id, 4);
+        assert!(socket.recv_from(&mut buf).is_err());
+
+        cancel.cancel();
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let lookup = || -> Result<Vec<IpAddr>, String> { Ok(vec![]) };
+        let reply = respond(&config(None), &lookup, &[0, 9, 1]);
+        let reply = parse_response(&reply).unwrap();
+
+        assert_eq!(reply.id, 9);
+        assert_eq!(reply.rcode, RCODE_FORMERR);
+    }
+}
@@ -0,0 +1,287 @@
+/// how a file's bytes map to text, kept so a file is written back the way it was read
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// utf-8 starting with `EF BB BF`, as notepad used to save it
+    Utf8Bom,
+    /// `bom` is whether the file started with `FF FE`
+    Utf16Le { bom: bool },
+    /// `bom` is whether the file started with `FE FF`
+    Utf16Be { bom: bool },
+    /// the windows "ANSI" code page, taken to be windows-1252
+    Ansi,
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8 with bom",
+            Encoding::Utf16Le { bom: true } => "utf-16le with bom",
+            Encoding::Utf16Le { bom: false } => "utf-16le",
+            Encoding::Utf16Be { bom: true } => "utf-16be with bom",
+            Encoding::Utf16Be { bom: false } => "utf-16be",
+            Encoding::Ansi => "windows-1252",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// windows-1252 from 0x80 to 0x9f, the rest of the code page matches latin-1. the five
+/// holes have no character
+const CP1252_HIGH: [Option<char>; 32] = [
+    Some('\u{20AC}'),
+    None,
+    Some('\u{201A}'),
+    Some('\u{0192}'),
+    Some('\u{201E}'),
+    Some('\u{2026}'),
+    Some('\u{2020}'),
+    Some('\u{2021}'),
+    Some('\u{02C6}'),
+    Some('\u{2030}'),
+    Some('\u{0160}'),
+    Some('\u{2039}'),
+    Some('\u{0152}'),
+    None,
+    Some('\u{017D}'),
+    None,
+    None,
+    Some('\u{2018}'),
+    Some('\u{2019}'),
+    Some('\u{201C}'),
+    Some('\u{201D}'),
+    Some('\u{2022}'),
+    Some('\u{2013}'),
+    Some('\u{2014}'),
+    Some('\u{02DC}'),
+    Some('\u{2122}'),
+    Some('\u{0161}'),
+    Some('\u{203A}'),
+    Some('\u{0153}'),
+    None,
+    Some('\u{017E}'),
+    Some('\u{0178}'),
+];
+
+/// utf-16 without a bom shows up as mostly zero high bytes for the latin text a hosts
+/// file is mostly made of, while its low bytes are never zero
+fn guess_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 2 || bytes.len() % 2 == 1 {
+        return None;
+    }
+
+    let sample = &bytes[..bytes.len().min(512)];
+    let zeros = |offset: usize| {
+        sample
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    let half = sample.len() / 4;
+
+    match (zeros(0), zeros(1)) {
+        (0, odd) if odd > half => Some(Encoding::Utf16Le { bom: false }),
+        (even, 0) if even > half => Some(Encoding::Utf16Be { bom: false }),
+        _ => None,
+    }
+}
+
+/// the 1 based line `at` falls on, counting `\n` units before it
+fn line_at<T: PartialEq + From<u8>>(units: &[T], at: usize) -> usize {
+    units[..at].iter().filter(|u| **u == T::from(b'\n')).count() + 1
+}
+
+fn decode_utf16(bytes: &[u8], encoding: Encoding) -> Result<String, String> {
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| match (encoding, pair) {
+            (Encoding::Utf16Be { .. }, [hi, lo]) => u16::from_be_bytes([*hi, *lo]),
+            (_, [lo, hi]) => u16::from_le_bytes([*lo, *hi]),
+            // an odd trailing byte can't be a whole unit
+            _ => 0xFFFF,
+        })
+        .collect();
+
+    let mut text = String::with_capacity(units.len());
+    let mut at = 0;
+    for c in std::char::decode_utf16(units.iter().cloned()) {
+        match c {
+            Ok('\u{FFFF}') | Err(_) => {
+                return Err(format!(
+                    "line {} is not valid {}",
+                    line_at(&units, at),
+                    encoding
+                ))
+            }
+            Ok(c) => {
+                at += c.len_utf16();
+                text.push(c);
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+fn decode_ansi(bytes: &[u8]) -> Result<String, String> {
+    bytes
+        .iter()
+        .enumerate()
+        .map(|(i, b)| match b {
+            0x80..=0x9F => CP1252_HIGH[(*b - 0x80) as usize].ok_or_else(|| {
+                format!(
+                    "line {} has byte {:#04x}, which is neither utf-8 nor {}",
+                    line_at(bytes, i),
+                    b,
+                    Encoding::Ansi
+                )
+            }),
+            _ => Ok(*b as char),
+        })
+        .collect()
+}
+
+/// the text in `bytes` and the encoding it was in. a bom decides it, otherwise valid
+/// utf-8 is taken as such, zero bytes point to utf-16 and anything else is ansi. a line
+/// that doesn't decode is an error rather than being dropped
+pub fn decode(bytes: &[u8]) -> Result<(String, Encoding), String> {
+    let (encoding, body) = if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        (Encoding::Utf8Bom, rest)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+        (Encoding::Utf16Le { bom: true }, rest)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+        (Encoding::Utf16Be { bom: true }, rest)
+    } else if std::str::from_utf8(bytes).is_ok() && false == bytes.contains(&0) {
+        (Encoding::Utf8, bytes)
+    } else {
+        (guess_utf16(bytes).unwrap_or(Encoding::Ansi), bytes)
+    };
+
+    let text = match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => match std::str::from_utf8(body) {
+            Ok(text) => text.to_owned(),
+            Err(e) => {
+                return Err(format!(
+                    "line {} is not valid {}",
+                    line_at(body, e.valid_up_to()),
+                    encoding
+                ))
+            }
+        },
+        Encoding::Utf16Le { .. } | Encoding::Utf16Be { .. } => decode_utf16(body, encoding)?,
+        Encoding::Ansi => decode_ansi(body)?,
+    };
+
+    Ok((text, encoding))
+}
+
+/// `text` as bytes in `encoding`, with a bom only if it was read with one. a character the
+/// encoding can't hold is an error naming its line
+pub fn encode(text: &str, encoding: Encoding) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+
+    match encoding {
+        Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+        Encoding::Utf8Bom => {
+            bytes.extend_from_slice(UTF8_BOM);
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        Encoding::Utf16Le { bom } => {
+            if bom {
+                bytes.extend_from_slice(UTF16LE_BOM);
+            }
+            text.encode_utf16()
+                .for_each(|u| bytes.extend_from_slice(&u.to_le_bytes()));
+        }
+        Encoding::Utf16Be { bom } => {
+            if bom {
+                bytes.extend_from_slice(UTF16BE_BOM);
+            }
+            text.encode_utf16()
+                .for_each(|u| bytes.extend_from_slice(&u.to_be_bytes()));
+        }
+        Encoding::Ansi => {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    bytes.push(b'\n');
+                }
+                for c in line.chars() {
+                    let high = CP1252_HIGH.iter().position(|h| *h == Some(c));
+                    match (c as u32, high) {
+                        (_, Some(at)) => bytes.push(0x80 + at as u8),
+                        (n @ 0..=0x7F, _) | (n @ 0xA0..=0xFF, _) => bytes.push(n as u8),
+                        _ => {
+                            return Err(format!(
+                                "line {} has {:?}, which {} can't hold",
+                                i + 1,
+                                c,
+                                encoding
+                            ))
+                        }
+                    };
+                }
+            }
+        }
+    };
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTS: &str = "# caf\u{e9}\r\n127.0.0.1 localhost\r\n";
+
+    #[test]
+    fn round_trips_each_encoding() {
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf8Bom,
+            Encoding::Utf16Le { bom: true },
+            Encoding::Utf16Le { bom: false },
+            Encoding::Utf16Be { bom: true },
+            Encoding::Utf16Be { bom: false },
+            Encoding::Ansi,
+        ] {
+            let bytes = encode(HOSTS, encoding).unwrap();
+            assert_eq!(decode(&bytes).unwrap(), (HOSTS.to_owned(), encoding));
+        }
+    }
+
+    #[test]
+    fn detects_utf16_without_bom() {
+        let text = "# \u{4e2d}\u{6587}\n127.0.0.1 localhost\n";
+        let le: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        let be: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+
+        assert_eq!(
+            decode(&le).unwrap(),
+            (text.to_owned(), Encoding::Utf16Le { bom: false })
+        );
+        assert_eq!(
+            decode(&be).unwrap(),
+            (text.to_owned(), Encoding::Utf16Be { bom: false })
+        );
+    }
+
+    #[test]
+    fn reports_line_that_wont_decode() {
+        let err = decode(b"127.0.0.1 localhost\n# \x81\n").unwrap_err();
+        assert!(err.starts_with("line 2 "), "{}", err);
+
+        let err = decode(&[0xEF, 0xBB, 0xBF, b'a', b'\n', 0xC3]).unwrap_err();
+        assert_eq!(err, "line 2 is not valid utf-8 with bom");
+
+        let err = encode("a\n\u{4e2d}", Encoding::Ansi).unwrap_err();
+        assert!(err.starts_with("line 2 "), "{}", err);
+    }
+}
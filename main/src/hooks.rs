@@ -0,0 +1,264 @@
+use crate::{util, Cancel, Config};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub const DEFAULT_HOOK_TIMEOUT: u64 = 30;
+/// prefix of the variables describing the change
+pub const ENV_PREFIX: &str = "WSL2_IP_HOST_";
+
+fn default_timeout() -> u64 {
+    DEFAULT_HOOK_TIMEOUT
+}
+
+/// what a failing hook means for the ones after it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// carry on without reporting it
+    Ignore,
+    /// report it and carry on
+    #[default]
+    Warn,
+    /// report it and skip the remaining hooks
+    Stop,
+}
+
+/// a program run after every successful write
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hook {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// set on top of the `WSL2_IP_HOST_*` variables
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub on_failure: OnFailure,
+}
+
+impl Hook {
+    pub fn new(command: &str) -> Hook {
+        Hook {
+            command: command.to_owned(),
+            args: vec![],
+            env: BTreeMap::new(),
+            timeout_secs: DEFAULT_HOOK_TIMEOUT,
+            on_failure: OnFailure::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.command)?;
+        self.args.iter().try_for_each(|a| write!(f, " {}", a))
+    }
+}
+
+/// the change a hook is told about, as variables and as json on stdin
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Event {
+    /// the address our lines pointed at before the write, if there were any
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+    pub names: Vec<String>,
+    pub distro: Option<String>,
+    pub hosts_path: String,
+}
+
+impl Event {
+    pub fn new(config: &Config, old_ip: Option<String>, new_ip: &str) -> Event {
+        Event {
+            old_ip,
+            new_ip: new_ip.to_owned(),
+            names: config.expanded_names(),
+            distro: config.distro.to_owned(),
+            hosts_path: config.hosts_path.to_owned(),
+        }
+    }
+
+    pub fn changed(&self) -> bool {
+        self.old_ip.as_deref() != Some(&self.new_ip[..])
+    }
+
+    /// `WSL2_IP_HOST_*` variables, missing values are empty and names are comma separated
+    pub fn env(&self) -> Vec<(String, String)> {
+        let vars = vec![
+            ("OLD_IP", self.old_ip.to_owned().unwrap_or_default()),
+            ("NEW_IP", self.new_ip.to_owned()),
+            ("NAMES", self.names.join(",")),
+            ("DISTRO", self.distro.to_owned().unwrap_or_default()),
+            ("HOSTS_PATH", self.hosts_path.to_owned()),
+            ("CHANGED", (self.changed() as u8).to_string()),
+        ];
+
+        vars.into_iter()
+            .map(|(k, v)| (format!("{}{}", ENV_PREFIX, k), v))
+            .collect()
+    }
+}
+
+/// how a hook went, with whatever it printed
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub hook: Hook,
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// why the hook counts as failed: it couldn't start, timed out or exited non-zero
+    pub error: Option<String>,
+}
+
+impl Outcome {
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// the message to show for a failure the hook's policy wants reported
+    pub fn warning(&self) -> Option<String> {
+        match (&self.error, self.hook.on_failure) {
+            (Some(_), OnFailure::Ignore) | (None, _) => None,
+            (Some(e), OnFailure::Stop) => Some(format!(
+                "Hook {} failed, so the hooks after it were skipped: {}",
+                self.hook, e
+            )),
+            (Some(e), OnFailure::Warn) => Some(format!("Hook {} failed: {}", self.hook, e)),
+        }
+    }
+}
+
+/// runs a single hook to completion or until its timeout
+pub fn run(hook: &Hook, event: &Event, cancel: &Cancel) -> Outcome {
+    let mut cmd = util::hidden_cmd(&hook.command);
+    cmd.args(&hook.args).envs(event.env()).envs(&hook.env);
+
+    let input = serde_json::to_vec(event).unwrap_or_default();
+    let timeout = Duration::from_secs(hook.timeout_secs);
+
+    let mut outcome = Outcome {
+        hook: hook.clone(),
+        status: None,
+        stdout: String::new(),
+        stderr: String::new(),
+        error: None,
+    };
+
+    match util::run_with_input(cmd, Some(input), timeout, cancel) {
+        Ok(output) => {
+            outcome.status = output.status.code();
+            outcome.stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            outcome.stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            if false == output.status.success() {
+                outcome.error = Some(match outcome.stderr.trim() {
+                    "" => format!("exited with {}", output.status),
+                    e => e.to_owned(),
+                });
+            }
+        }
+        Err(e) => outcome.error = Some(format!("{}", e)),
+    };
+
+    match &outcome.error {
+        Some(e) => log::warn!("hook {} failed: {}", hook, e),
+        None => log::info!("ran hook {}", hook),
+    };
+    outcome
+}
+
+/// runs `hooks` in order. a failing hook set to `Stop` is the last one run. the files
+/// were written before any hook ran, so failures are left in the outcomes for the
+/// caller to report rather than failing the write
+pub fn run_all(hooks: &[Hook], event: &Event, cancel: &Cancel) -> Vec<Outcome> {
+    let mut outcomes = vec![];
+
+    for hook in hooks {
+        let outcome = run(hook, event, cancel);
+        let stop = outcome.failed() && hook.on_failure == OnFailure::Stop;
+        outcomes.push(outcome);
+
+        if stop {
+            break;
+        }
+    }
+
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MISSING: &str = "wsl2-ip-host-test-hook-that-does-not-exist";
+
+    fn event() -> Event {
+        let mut config = Config::new();
+        config.set_names(vec!["host.wsl.internal".to_owned(), "api.wsl".to_owned()]);
+        config.distro = Some("Ubuntu".to_owned());
+        Event::new(&config, Some("172.20.1.2".to_owned()), "172.20.9.9")
+    }
+
+    fn hook(on_failure: OnFailure) -> Hook {
+        let mut hook = Hook::new(MISSING);
+        hook.on_failure = on_failure;
+        hook
+    }
+
+    #[test]
+    fn describes_change_in_env() {
+        let env = event().env();
+        let get = |k: &str| {
+            env.iter()
+                .find(|(name, _)| name == &format!("{}{}", ENV_PREFIX, k))
+                .map(|(_, v)| v.to_owned())
+        };
+
+        assert_eq!(get("OLD_IP"), Some("172.20.1.2".to_owned()));
+        assert_eq!(get("NEW_IP"), Some("172.20.9.9".to_owned()));
+        assert_eq!(get("NAMES"), Some("host.wsl.internal,api.wsl".to_owned()));
+        assert_eq!(get("DISTRO"), Some("Ubuntu".to_owned()));
+        assert_eq!(get("CHANGED"), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn same_ip_is_not_a_change() {
+        let mut event = event();
+        event.old_ip = Some(event.new_ip.to_owned());
+        assert!(!event.changed());
+
+        event.old_ip = None;
+        assert!(event.changed());
+    }
+
+    #[test]
+    fn reads_hook_with_defaults() {
+        let hook: Hook = serde_json::from_str(r#"{ "command": "nginx.exe" }"#).unwrap();
+        assert_eq!(hook, Hook::new("nginx.exe"));
+
+        let hook: Hook = serde_json::from_str(
+            r#"{ "command": "a", "args": ["-s", "reload"], "on_failure": "stop" }"#,
+        )
+        .unwrap();
+        assert_eq!(hook.on_failure, OnFailure::Stop);
+        assert_eq!(hook.to_string(), "a -s reload");
+    }
+
+    #[test]
+    fn failure_policy_decides_what_happens_next() {
+        let cancel = Cancel::new();
+        let hooks = vec![hook(OnFailure::Ignore), hook(OnFailure::Warn)];
+        let outcomes = run_all(&hooks, &event(), &cancel);
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|o| o.failed()));
+        assert_eq!(outcomes[0].warning(), None);
+        assert!(outcomes[1].warning().unwrap().contains(MISSING));
+
+        let hooks = vec![hook(OnFailure::Stop), hook(OnFailure::Warn)];
+        let outcomes = run_all(&hooks, &event(), &cancel);
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes[0].warning().unwrap().contains("skipped"));
+    }
+}
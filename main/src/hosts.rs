@@ -0,0 +1,707 @@
+use serde::{Deserialize, Serialize};
+
+/// marks every line this tool manages so it can be found and replaced on the next write
+pub const HOSTS_COMMENT: &str = "# added by wsl2-ip-host";
+/// put in front of someone else's line we commented out, so `remove` can put it back
+pub const DISABLED_TAG: &str = "# disabled by wsl2-ip-host: ";
+/// marks the copy of a line we commented out that keeps its other names working, it
+/// goes when `remove` puts the original back
+pub const KEPT_COMMENT: &str = "# kept by wsl2-ip-host";
+/// marks our lines for `static_entries`, which don't point at the distro
+pub const STATIC_COMMENT: &str = "# added by wsl2-ip-host (static)";
+/// names the windows resolver reliably reads from one line, the rest of a longer line
+/// can be ignored
+pub const MAX_NAMES_PER_LINE: usize = 9;
+
+/// sections other programs keep in the hosts file as (owner, first line, last line).
+/// we never change, move or drop a line inside one
+pub const KNOWN_BLOCKS: &[(&str, &str, &str)] = &[
+    (
+        "Docker Desktop",
+        "# Added by Docker Desktop",
+        "# End of section",
+    ),
+    (
+        "Docker for Windows",
+        "# Added by Docker for Windows",
+        "# End of section",
+    ),
+    (
+        "OpenVPN Connect",
+        "# BEGIN section for OpenVPN Client SSL sites",
+        "# END section for OpenVPN Client SSL sites",
+    ),
+    ("Local", "## Local - Start ##", "## Local - End ##"),
+    (
+        "vagrant-hostmanager",
+        "## vagrant-hostmanager-start",
+        "## vagrant-hostmanager-end",
+    ),
+];
+
+/// another program's section of the hosts file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub owner: String,
+    /// 1 based, the marker lines included
+    pub first: usize,
+    pub last: usize,
+    /// false when the end marker is missing and the block runs to the end of the file
+    pub closed: bool,
+}
+
+impl std::fmt::Display for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on lines {}-{}", self.owner, self.first, self.last)?;
+        match self.closed {
+            true => Ok(()),
+            false => write!(f, " (never closed)"),
+        }
+    }
+}
+
+/// the known blocks in `lines`. one that never ends runs to the end of the file, so a
+/// broken block is left alone rather than having lines taken out of it
+pub fn blocks(lines: &[String]) -> Vec<Block> {
+    let mut list = vec![];
+    let mut open: Option<(&str, &str, usize)> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+
+        match open {
+            Some((owner, end, first)) if line.starts_with(end) => {
+                list.push(Block {
+                    owner: owner.to_owned(),
+                    first,
+                    last: i + 1,
+                    closed: true,
+                });
+                open = None;
+            }
+            Some(_) => (),
+            None => {
+                open = KNOWN_BLOCKS
+                    .iter()
+                    .find(|(_, begin, _)| line.starts_with(begin))
+                    .map(|(owner, _, end)| (*owner, *end, i + 1));
+            }
+        };
+    }
+
+    if let Some((owner, _, first)) = open {
+        list.push(Block {
+            owner: owner.to_owned(),
+            first,
+            last: lines.len(),
+            closed: false,
+        });
+    }
+
+    list
+}
+
+/// the block the 0 based line `i` sits in
+fn block_at(blocks: &[Block], i: usize) -> Option<&Block> {
+    blocks.iter().find(|b| b.first <= i + 1 && i < b.last)
+}
+
+/// what to do about a name that another line of the hosts file also maps
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnConflict {
+    /// write ours anyway and report it, the resolver takes whichever line comes first
+    #[default]
+    Warn,
+    /// leave the name to the other line
+    Skip,
+    /// comment the other line out until `remove`
+    CommentOut,
+}
+
+named_enum!(OnConflict, "conflict policy", {
+    Warn => "warn",
+    Skip => "skip",
+    CommentOut => "comment-out",
+});
+
+/// names pointed at one address, e.g. a static entry such as `127.0.0.1 local.myapp.test`
+/// or the names whose source resolved to the same address
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mapping {
+    pub ip: String,
+    pub names: Vec<String>,
+}
+
+impl Mapping {
+    pub fn new(ip: &str, names: &[String]) -> Mapping {
+        Mapping {
+            ip: ip.to_owned(),
+            names: names.to_owned(),
+        }
+    }
+
+    /// an address and names the hosts file can hold, wildcards aren't
+    pub fn check(&self) -> Result<(), String> {
+        if self.ip.parse::<std::net::IpAddr>().is_err() {
+            return Err(format!("{} is not an IP address.", self.ip));
+        }
+        if self.names.is_empty() {
+            return Err(format!("Entry for {} has no host names.", self.ip));
+        }
+
+        for name in self.names.iter() {
+            if crate::names::is_wildcard(name) {
+                return Err(format!("{} can't be a wildcard here.", name));
+            }
+            crate::names::check(name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `ip=name[,name...]`, the form used on the command line and when handing mappings to
+/// the writer
+impl std::str::FromStr for Mapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mapping, String> {
+        let (ip, names) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid entry {}, expected ip=name", s))?;
+        let entry = Mapping {
+            ip: ip.to_owned(),
+            names: names
+                .split(',')
+                .filter(|n| false == n.is_empty())
+                .map(|n| n.to_owned())
+                .collect(),
+        };
+
+        entry.check()?;
+        Ok(entry)
+    }
+}
+
+impl std::fmt::Display for Mapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.ip, self.names.join(","))
+    }
+}
+
+/// how our names are laid out in the hosts file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineStyle {
+    /// `ip name` for each name
+    #[default]
+    PerName,
+    /// `ip name name ...`, starting a new line after `MAX_NAMES_PER_LINE` names
+    Grouped,
+}
+
+named_enum!(LineStyle, "line style", {
+    PerName => "per-name",
+    Grouped => "grouped",
+});
+
+impl LineStyle {
+    /// names written on each line
+    pub fn names_per_line(&self) -> usize {
+        match self {
+            LineStyle::PerName => 1,
+            LineStyle::Grouped => MAX_NAMES_PER_LINE,
+        }
+    }
+}
+
+/// a line we don't manage that maps one of our names
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    /// 1 based
+    pub line: usize,
+    pub text: String,
+    pub name: String,
+    /// the program whose block the line is in, such lines are never commented out
+    pub owner: Option<String>,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Line {} of the hosts file ({}) also maps {}",
+            self.line,
+            self.text.trim(),
+            self.name
+        )?;
+        match &self.owner {
+            Some(owner) => write!(f, " in {}'s block", owner),
+            None => Ok(()),
+        }
+    }
+}
+
+/// the names an entry maps, nothing for a comment or blank line
+fn entry_names(line: &str) -> Vec<&str> {
+    let entry = line.split('#').next().unwrap_or("");
+    entry.split_whitespace().skip(1).collect()
+}
+
+/// every line outside ours that maps one of `names`
+pub fn conflicts(names: &[String], lines: &[String]) -> Vec<Conflict> {
+    let blocks = blocks(lines);
+    let mut list = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let owner = block_at(&blocks, i).map(|b| b.owner.to_owned());
+        if owner.is_none() && line.contains(HOSTS_COMMENT) {
+            continue;
+        }
+
+        for entry in entry_names(line) {
+            if let Some(name) = names.iter().find(|n| n.eq_ignore_ascii_case(entry)) {
+                list.push(Conflict {
+                    line: i + 1,
+                    text: line.to_owned(),
+                    name: name.to_owned(),
+                    owner: owner.to_owned(),
+                });
+            }
+        }
+    }
+
+    list
+}
+
+/// `lines` with each line in `conflicts` commented out and tagged, except those in
+/// another program's block. a line that maps other names too is followed by a copy
+/// without ours, so only the conflicting names are taken away
+pub fn disable(lines: &[String], conflicts: &[Conflict]) -> Vec<String> {
+    let mut list = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let ours: Vec<&str> = conflicts
+            .iter()
+            .filter(|c| c.line == i + 1 && c.owner.is_none())
+            .map(|c| &c.name[..])
+            .collect();
+        if ours.is_empty() {
+            list.push(line.to_owned());
+            continue;
+        }
+
+        list.push(format!("{}{}", DISABLED_TAG, line));
+
+        let others: Vec<&str> = entry_names(line)
+            .into_iter()
+            .filter(|e| false == ours.iter().any(|n| n.eq_ignore_ascii_case(e)))
+            .collect();
+        if let (Some(ip), false) = (line.split_whitespace().next(), others.is_empty()) {
+            list.push(format!("{} {} {}", ip, others.join(" "), KEPT_COMMENT));
+        }
+    }
+
+    list
+}
+
+/// uncomments the lines `disable` commented out and drops the copies it kept
+pub fn restore(lines: &[String]) -> Vec<String> {
+    let blocks = blocks(lines);
+
+    lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| match block_at(&blocks, i) {
+            Some(_) => Some(line.to_owned()),
+            None => {
+                let line = line.strip_prefix(DISABLED_TAG).unwrap_or(line);
+                match line.contains(KEPT_COMMENT) {
+                    true => None,
+                    false => Some(line.to_owned()),
+                }
+            }
+        })
+        .collect()
+}
+
+/// the lines we manage, with their 0 based index. a line in another program's block is
+/// never ours whatever it says
+fn managed(lines: &[String]) -> Vec<(usize, &String)> {
+    let blocks = blocks(lines);
+
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(i, line)| line.contains(HOSTS_COMMENT) && block_at(&blocks, *i).is_none())
+        .collect()
+}
+
+/// the lines of a hosts file without the ones we manage
+pub fn clean(lines: &[String]) -> Vec<String> {
+    let ours: Vec<usize> = managed(lines).iter().map(|(i, _)| *i).collect();
+
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !ours.contains(i))
+        .map(|(_, line)| line.to_owned())
+        .collect()
+}
+
+/// the address of the first managed line that isn't a static entry
+pub fn managed_ip(lines: &[String]) -> Option<String> {
+    managed(lines)
+        .iter()
+        .filter(|(_, line)| false == line.contains(STATIC_COMMENT))
+        .find_map(|(_, line)| line.split_whitespace().next())
+        .map(|ip| ip.to_owned())
+}
+
+/// what the hosts file holds as far as we're concerned
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    /// our lines, without the marker comment
+    pub managed: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+    /// other programs' sections, which are left as they are
+    pub blocks: Vec<Block>,
+}
+
+pub fn status(names: &[String], lines: &[String]) -> Status {
+    Status {
+        managed: managed(lines)
+            .iter()
+            .map(|(_, line)| line.replace(HOSTS_COMMENT, "").trim().to_owned())
+            .collect(),
+        conflicts: conflicts(names, lines),
+        blocks: blocks(lines),
+    }
+}
+
+/// our managed lines mapping `names` to `ip`, laid out as `style` says
+pub fn entries(ip: &str, names: &[String], style: LineStyle) -> Vec<String> {
+    names
+        .chunks(style.names_per_line())
+        .map(|chunk| format!("{} {} {}", ip, chunk.join(" "), HOSTS_COMMENT))
+        .collect()
+}
+
+/// our lines for `statics`, marked apart from the distro's
+pub fn static_entries(statics: &[Mapping], style: LineStyle) -> Vec<String> {
+    statics
+        .iter()
+        .flat_map(|entry| {
+            entry
+                .names
+                .chunks(style.names_per_line())
+                .map(move |chunk| format!("{} {} {}", entry.ip, chunk.join(" "), STATIC_COMMENT))
+        })
+        .collect()
+}
+
+/// appends `ours`, or puts them ahead of a block that never closes since anything after
+/// it would count as part of it
+pub fn insert(lines: &[String], ours: Vec<String>) -> Vec<String> {
+    let mut list = lines.to_owned();
+    let at = match blocks(lines).last() {
+        Some(block) if false == block.closed => block.first - 1,
+        _ => list.len(),
+    };
+
+    list.splice(at..at, ours);
+
+    list
+}
+
+/// appends our lines for `names`
+pub fn apply(ip: &str, names: &[String], lines: &[String], style: LineStyle) -> Vec<String> {
+    insert(lines, entries(ip, names, style))
+}
+
+/// replaces whatever we managed before with a fresh line per name
+pub fn update(ip: &str, names: &[String], lines: &[String]) -> Vec<String> {
+    apply(ip, names, &clean(lines), LineStyle::PerName)
+}
+
+/// replaces whatever we managed before with a line per name of each mapping, or lines
+/// laid out as `style` says, after dealing with the names other lines map as `policy`
+/// says
+pub fn update_with_policy(
+    mappings: &[Mapping],
+    lines: &[String],
+    policy: OnConflict,
+    style: LineStyle,
+) -> Vec<String> {
+    let names: Vec<String> = mappings.iter().flat_map(|m| m.names.to_owned()).collect();
+    let found = conflicts(&names, lines);
+
+    let (mappings, lines) = match policy {
+        OnConflict::Warn => (mappings.to_vec(), clean(lines)),
+        OnConflict::Skip => {
+            let mappings = mappings
+                .iter()
+                .map(|m| Mapping {
+                    ip: m.ip.to_owned(),
+                    names: m
+                        .names
+                        .iter()
+                        .filter(|n| !found.iter().any(|c| &c.name == *n))
+                        .cloned()
+                        .collect(),
+                })
+                .collect();
+            (mappings, clean(lines))
+        }
+        OnConflict::CommentOut => (mappings.to_vec(), clean(&disable(lines, &found))),
+    };
+
+    let ours = mappings
+        .iter()
+        .flat_map(|m| entries(&m.ip, &m.names, style))
+        .collect();
+    insert(&lines, ours)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|l| l.to_owned()).collect()
+    }
+
+    #[test]
+    fn replaces_managed_lines_only() {
+        let before = lines(
+            "127.0.0.1 localhost
+172.20.1.2 host.wsl.internal # added by wsl2-ip-host
+10.0.0.5 nas.local",
+        );
+
+        assert_eq!(
+            update("172.20.9.9", &["host.wsl.internal".to_owned()], &before),
+            lines(
+                "127.0.0.1 localhost
+10.0.0.5 nas.local
+172.20.9.9 host.wsl.internal # added by wsl2-ip-host"
+            )
+        );
+    }
+
+    #[test]
+    fn finds_managed_ip() {
+        let list = lines(
+            "127.0.0.1 localhost
+172.20.1.2 host.wsl.internal # added by wsl2-ip-host",
+        );
+
+        assert_eq!(managed_ip(&list), Some("172.20.1.2".to_owned()));
+        assert_eq!(managed_ip(&list[..1]), None);
+    }
+
+    #[test]
+    fn groups_names_up_to_the_line_limit() {
+        let names: Vec<String> = (1..=11).map(|n| format!("n{}.wsl", n)).collect();
+        let before = lines("127.0.0.1 localhost\n172.20.1.1 n1.wsl # added by wsl2-ip-host");
+
+        let list = update_with_policy(
+            &[Mapping::new("172.20.1.2", &names)],
+            &before,
+            OnConflict::Warn,
+            LineStyle::Grouped,
+        );
+
+        assert_eq!(
+            list,
+            vec![
+                "127.0.0.1 localhost".to_owned(),
+                format!("172.20.1.2 {} {}", names[..9].join(" "), HOSTS_COMMENT),
+                format!("172.20.1.2 n10.wsl n11.wsl {}", HOSTS_COMMENT),
+            ]
+        );
+        assert_eq!(managed_ip(&list), Some("172.20.1.2".to_owned()));
+        assert_eq!(update("172.20.1.2", &names, &list).len(), 12);
+        assert_eq!("grouped".parse(), Ok(LineStyle::Grouped));
+    }
+
+    #[test]
+    fn writes_static_entries_after_ours() {
+        let entry: Mapping = "127.0.0.1=local.myapp.test,api.myapp.test".parse().unwrap();
+        let names = vec!["host.wsl.internal".to_owned()];
+
+        let ours = update("172.20.1.2", &names, &lines("127.0.0.1 localhost"));
+        let list = insert(&ours, static_entries(&[entry], LineStyle::Grouped));
+
+        assert_eq!(
+            &list[2..],
+            &[format!(
+                "127.0.0.1 local.myapp.test api.myapp.test {}",
+                STATIC_COMMENT
+            )]
+        );
+        assert_eq!(managed_ip(&list[2..]), None);
+        assert_eq!(managed_ip(&list), Some("172.20.1.2".to_owned()));
+        assert_eq!(clean(&list), lines("127.0.0.1 localhost"));
+
+        assert!("127.0.0.1".parse::<Mapping>().is_err());
+        assert!("localhost=a.test".parse::<Mapping>().is_err());
+        assert!("127.0.0.1=*.myapp.test".parse::<Mapping>().is_err());
+        assert!("127.0.0.1=".parse::<Mapping>().is_err());
+    }
+
+    const DOCKER_DESKTOP: &str = include_str!("../tests/fixtures/docker-desktop.hosts");
+    const OPENVPN_LOCAL: &str = include_str!("../tests/fixtures/openvpn-local.hosts");
+    const DOCKER_UNTERMINATED: &str = include_str!("../tests/fixtures/docker-unterminated.hosts");
+
+    /// the lines of `block` in `list`
+    fn block_lines(list: &[String], block: &Block) -> Vec<String> {
+        list[block.first - 1..block.last].to_vec()
+    }
+
+    #[test]
+    fn leaves_docker_desktop_block_alone() {
+        let original = lines(DOCKER_DESKTOP);
+        let names = vec![
+            "host.wsl.internal".to_owned(),
+            "host.docker.internal".to_owned(),
+        ];
+        let found = blocks(&original);
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].owner, "Docker Desktop");
+        assert_eq!(original[found[0].last - 1], "# End of section");
+
+        let list = update_with_policy(
+            &[Mapping::new("172.20.1.2", &names)],
+            &original,
+            OnConflict::CommentOut,
+            LineStyle::PerName,
+        );
+        assert_eq!(
+            block_lines(&list, &found[0]),
+            block_lines(&original, &found[0])
+        );
+        assert_eq!(
+            status(&names, &list).conflicts[0].owner,
+            Some("Docker Desktop".to_owned())
+        );
+        assert_eq!(restore(&clean(&list)), original);
+    }
+
+    #[test]
+    fn keeps_marker_lines_inside_other_blocks() {
+        let original = lines(OPENVPN_LOCAL);
+        let names = vec!["host.wsl.internal".to_owned()];
+        let before = status(&names, &original);
+
+        assert_eq!(
+            before
+                .blocks
+                .iter()
+                .map(|b| &b.owner[..])
+                .collect::<Vec<&str>>(),
+            vec!["OpenVPN Connect", "Local"]
+        );
+        assert_eq!(before.managed, vec!["172.20.1.2 host.wsl.internal"]);
+        assert_eq!(managed_ip(&original), Some("172.20.1.2".to_owned()));
+
+        let list = update("172.20.9.9", &names, &original);
+        assert!(list.contains(&"127.0.0.1 shop.local # added by wsl2-ip-host".to_owned()));
+        assert_eq!(
+            status(&names, &list).managed,
+            vec!["172.20.9.9 host.wsl.internal"]
+        );
+
+        let mut expected = original.clone();
+        expected.remove(1);
+        assert_eq!(clean(&list), expected);
+    }
+
+    #[test]
+    fn writes_ahead_of_a_block_that_never_closes() {
+        let original = lines(DOCKER_UNTERMINATED);
+        let names = vec!["host.wsl.internal".to_owned()];
+        let found = blocks(&original);
+
+        assert!(!found[0].closed);
+        assert_eq!(found[0].last, 4);
+        assert_eq!(managed_ip(&original), None);
+
+        let list = update("172.20.1.2", &names, &original);
+        assert_eq!(
+            list[1],
+            "172.20.1.2 host.wsl.internal # added by wsl2-ip-host"
+        );
+        assert_eq!(update("172.20.1.2", &names, &list), list);
+    }
+
+    const FOREIGN: &str = "127.0.0.1 localhost
+10.0.0.5 host.wsl.internal nas.local # mine
+# 10.0.0.6 other.wsl.internal
+172.20.1.2 other.wsl.internal # added by wsl2-ip-host";
+
+    fn names() -> Vec<String> {
+        vec![
+            "Host.WSL.internal".to_owned(),
+            "other.wsl.internal".to_owned(),
+        ]
+    }
+
+    #[test]
+    fn finds_names_mapped_by_other_lines() {
+        let found = conflicts(&names(), &lines(FOREIGN));
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line, 2);
+        assert_eq!(found[0].name, "Host.WSL.internal");
+    }
+
+    #[test]
+    fn skips_conflicting_names() {
+        let list = update_with_policy(
+            &[Mapping::new("172.20.9.9", &names())],
+            &lines(FOREIGN),
+            OnConflict::Skip,
+            LineStyle::PerName,
+        );
+
+        assert_eq!(&list[..3], &lines(FOREIGN)[..3]);
+        assert_eq!(
+            &list[3..],
+            &["172.20.9.9 other.wsl.internal # added by wsl2-ip-host".to_owned()]
+        );
+    }
+
+    #[test]
+    fn comments_out_and_restores_conflicting_lines() {
+        let list = update_with_policy(
+            &[Mapping::new("172.20.9.9", &names())],
+            &lines(FOREIGN),
+            OnConflict::CommentOut,
+            LineStyle::PerName,
+        );
+
+        assert_eq!(
+            &list[1..3],
+            &[
+                "# disabled by wsl2-ip-host: 10.0.0.5 host.wsl.internal nas.local # mine"
+                    .to_owned(),
+                "10.0.0.5 nas.local # kept by wsl2-ip-host".to_owned(),
+            ]
+        );
+        assert!(conflicts(&names(), &list).is_empty());
+        assert_eq!(restore(&clean(&list)), lines(FOREIGN)[..3].to_vec());
+
+        // a line of nothing but our names has no copy, and a copy that conflicts later
+        // still restores to the original
+        let list = disable(&list, &conflicts(&["nas.local".to_owned()], &list));
+        assert_eq!(
+            list[2],
+            "# disabled by wsl2-ip-host: 10.0.0.5 nas.local # kept by wsl2-ip-host"
+        );
+        assert_eq!(list[3], "# 10.0.0.6 other.wsl.internal");
+        assert_eq!(restore(&clean(&list)), lines(FOREIGN)[..3].to_vec());
+    }
+}
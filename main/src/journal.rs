@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// file name of the journal in the user's home folder, next to the settings
+pub const JOURNAL_NAME: &str = ".wsl2-ip-host.journal.jsonl";
+/// size the journal may reach before it is trimmed
+pub const MAX_BYTES: u64 = 512 * 1024;
+/// size the newest entries are trimmed down to, half of `MAX_BYTES` so a trim isn't
+/// needed again for a good while
+pub const KEEP_BYTES: u64 = MAX_BYTES / 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    /// every target already had the address
+    Unchanged,
+    Wrote,
+    /// wsl uses mirrored networking and `mirrored` is `skip`
+    Skipped,
+    Failed,
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Decision::Unchanged => "unchanged",
+            Decision::Wrote => "wrote",
+            Decision::Skipped => "skipped",
+            Decision::Failed => "failed",
+        };
+        f.pad(text)
+    }
+}
+
+/// a line of the journal, one per discovery and write
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// seconds since the unix epoch
+    pub time: u64,
+    pub distro: Option<String>,
+    /// the discovery strategy that found the address
+    #[serde(default)]
+    pub strategy: Option<String>,
+    /// what the hosts file pointed at before
+    #[serde(default)]
+    pub old_ip: Option<String>,
+    #[serde(default)]
+    pub new_ip: Option<String>,
+    pub decision: Decision,
+    /// the targets that were rewritten
+    #[serde(default)]
+    pub targets: Vec<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl Entry {
+    pub fn new(distro: Option<String>, old_ip: Option<String>) -> Entry {
+        Entry {
+            time: now(),
+            distro,
+            strategy: None,
+            old_ip,
+            new_ip: None,
+            decision: Decision::Unchanged,
+            targets: vec![],
+            error: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ip = |ip: &Option<String>| ip.to_owned().unwrap_or_else(|| "-".to_owned());
+
+        write!(
+            f,
+            "{}  {}  {:<9}  {} -> {}",
+            format_time(self.time),
+            self.distro.as_deref().unwrap_or("(default)"),
+            self.decision,
+            ip(&self.old_ip),
+            ip(&self.new_ip)
+        )?;
+        if let Some(strategy) = &self.strategy {
+            write!(f, "  ({})", strategy)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, "  {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in utc
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // civil_from_days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// which entries to return, newest last
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    pub distro: Option<String>,
+    /// only entries at or after this many seconds since the unix epoch
+    pub since: Option<u64>,
+    /// only entries with one of these decisions, any when empty
+    pub decisions: Vec<Decision>,
+    /// the most recent this many of what matches
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        (self.distro.is_none() || entry.distro == self.distro)
+            && entry.time >= self.since.unwrap_or(0)
+            && (self.decisions.is_empty() || self.decisions.contains(&entry.decision))
+    }
+
+    pub fn filter(&self, entries: Vec<Entry>) -> Vec<Entry> {
+        let mut list: Vec<Entry> = entries.into_iter().filter(|e| self.matches(e)).collect();
+
+        if let Some(limit) = self.limit {
+            let skip = list.len().saturating_sub(limit);
+            list.drain(..skip);
+        }
+
+        list
+    }
+}
+
+/// entries from the lines of a journal. a line that doesn't parse, like one cut short
+/// when the machine went down mid write, is passed over rather than losing the rest
+pub fn parse(text: &str) -> Vec<Entry> {
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// an append only json lines file
+#[derive(Clone, Debug)]
+pub struct Journal {
+    pub path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(path: &Path) -> Journal {
+        Journal {
+            path: path.to_owned(),
+        }
+    }
+
+    /// `~/.wsl2-ip-host.journal.jsonl`
+    pub fn open_default() -> Result<Journal, String> {
+        match home::home_dir() {
+            Some(p) => Ok(Journal::new(&p.join(JOURNAL_NAME))),
+            None => Err("Unable to locate home folder.".to_owned()),
+        }
+    }
+
+    pub fn append(&self, entry: &Entry) -> Result<(), String> {
+        let mut line = serde_json::to_string(entry).map_err(|e| format!("{}", e))?;
+        line.push('\n');
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Unable to open {}: {}", self.path.display(), e))?;
+        file.write_all(line.as_bytes())
+            .map_err(|e| format!("Unable to write {}: {}", self.path.display(), e))?;
+
+        match file.metadata() {
+            Ok(m) if m.len() > MAX_BYTES => self.trim(KEEP_BYTES),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn entries(&self) -> Result<Vec<Entry>, String> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => Ok(parse(&text)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(format!("Unable to read {}: {}", self.path.display(), e)),
+        }
+    }
+
+    pub fn query(&self, query: &Query) -> Result<Vec<Entry>, String> {
+        Ok(query.filter(self.entries()?))
+    }
+
+    /// keeps the newest entries that fit in `budget` bytes. the trimmed journal is
+    /// renamed over the old one, so a failed trim loses nothing
+    fn trim(&self, budget: u64) -> Result<(), String> {
+        let mut lines: Vec<String> = vec![];
+        let mut size = 0;
+
+        for entry in self.entries()?.iter().rev() {
+            let line = match serde_json::to_string(entry) {
+                Ok(line) => line + "\n",
+                Err(_) => continue,
+            };
+            size += line.len() as u64;
+            if size > budget {
+                break;
+            }
+            lines.push(line);
+        }
+
+        let text: String = lines.into_iter().rev().collect();
+        crate::target::replace(&self.path, text.as_bytes())
+            .map_err(|e| format!("Unable to write {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: u64, distro: &str, decision: Decision) -> Entry {
+        let mut entry = Entry::new(Some(distro.to_owned()), None);
+        entry.time = time;
+        entry.decision = decision;
+        entry
+    }
+
+    #[test]
+    fn formats_utc_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(1709210096), "2024-02-29 12:34:56");
+    }
+
+    #[test]
+    fn skips_broken_lines() {
+        let line = serde_json::to_string(&entry(1, "Ubuntu", Decision::Wrote)).unwrap();
+        let text = format!("{}\n{{\"time\": 2, \"dis\n{}\n", line, line);
+
+        assert_eq!(parse(&text).len(), 2);
+    }
+
+    #[test]
+    fn queries_by_distro_decision_and_limit() {
+        let entries = vec![
+            entry(1, "Ubuntu", Decision::Wrote),
+            entry(2, "Debian", Decision::Failed),
+            entry(3, "Ubuntu", Decision::Unchanged),
+            entry(4, "Ubuntu", Decision::Wrote),
+        ];
+
+        let query = Query {
+            distro: Some("Ubuntu".to_owned()),
+            decisions: vec![Decision::Wrote],
+            ..Query::default()
+        };
+        let times = |list: Vec<Entry>| list.iter().map(|e| e.time).collect::<Vec<u64>>();
+        assert_eq!(times(query.filter(entries.clone())), vec![1, 4]);
+
+        let query = Query {
+            since: Some(2),
+            limit: Some(2),
+            ..Query::default()
+        };
+        assert_eq!(times(query.filter(entries)), vec![3, 4]);
+    }
+
+    #[test]
+    fn appends_and_reads_back() {
+        let path = std::env::temp_dir().join(format!("wsl2-ip-host-{}.jsonl", std::process::id()));
+        let journal = Journal::new(&path);
+
+        journal
+            .append(&entry(1, "Ubuntu", Decision::Wrote))
+            .unwrap();
+        journal
+            .append(&entry(2, "Ubuntu", Decision::Failed))
+            .unwrap();
+
+        let entries = journal.entries().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].decision, Decision::Failed);
+    }
+
+    #[test]
+    fn trims_to_the_newest_entries_that_fit() {
+        let dir = std::env::temp_dir().join(format!("wsl2-ip-host-trim-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let journal = Journal::new(&dir.join(JOURNAL_NAME));
+
+        for time in 101..=110 {
+            journal
+                .append(&entry(time, "Ubuntu", Decision::Wrote))
+                .unwrap();
+        }
+        let line = serde_json::to_string(&entry(101, "Ubuntu", Decision::Wrote)).unwrap();
+        journal.trim(3 * (line.len() as u64 + 1)).unwrap();
+
+        let times: Vec<u64> = journal.entries().unwrap().iter().map(|e| e.time).collect();
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(times, vec![108, 109, 110]);
+        assert_eq!(files, 1);
+    }
+}
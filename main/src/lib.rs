@@ -1,30 +1,234 @@
 use faccess::PathExt;
-use std::io::BufRead;
 use util::WRITER_EXE;
 
+/// `ALL`, `name()`, `Display` and `FromStr` for a setting picked by name, each variant
+/// given the kebab-case name it goes by in settings and on the command line. `what`
+/// describes the setting in the error for an unknown name
+macro_rules! named_enum {
+    ($enum:ident, $what:expr, { $($variant:ident => $name:expr),+ $(,)? }) => {
+        impl $enum {
+            pub const ALL: &'static [$enum] = &[$($enum::$variant),+];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($enum::$variant => $name),+
+                }
+            }
+        }
+
+        impl std::fmt::Display for $enum {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.pad(self.name())
+            }
+        }
+
+        impl std::str::FromStr for $enum {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<$enum, String> {
+                $enum::ALL
+                    .iter()
+                    .find(|v| v.name() == s)
+                    .copied()
+                    .ok_or_else(|| {
+                        let names: Vec<&str> = $enum::ALL.iter().map(|v| v.name()).collect();
+                        format!("Unknown {} {}, expected one of {}", $what, s, names.join(", "))
+                    })
+            }
+        }
+    };
+}
+
+mod discovery;
+pub mod distro;
+pub mod dns;
+pub mod encoding;
+pub mod hooks;
+pub mod hosts;
+pub mod journal;
+pub mod logging;
+pub mod names;
+pub mod portproxy;
+pub mod proc_net;
+mod reverse;
+pub mod settings;
+pub mod source;
+pub mod ssh;
+mod strategy;
+pub mod target;
+pub mod template;
+pub mod wslconfig;
+
+pub use discovery::{Cancel, Discovery, DistroResult, Found, DEFAULT_TIMEOUT};
+pub use distro::{OnStopped, DEFAULT_BOOT_TIMEOUT};
+pub use hooks::Hook;
+pub use hosts::{LineStyle, Mapping, OnConflict};
+pub use journal::Journal;
+pub use portproxy::PortForward;
+pub use reverse::{
+    find_vethernet_ip, find_windows_host_ip, preview_distro_hosts, write_distro_hosts,
+    DEFAULT_WINDOWS_HOST,
+};
+pub use source::{NameSource, Source};
+pub use ssh::{SshConfig, SshHost};
+pub use strategy::Strategy;
+pub use target::{Output, Target};
+pub use wslconfig::OnMirrored;
+
 mod util {
     const CREATE_NO_WINDOW: u32 = 0x08000000;
-    pub const HOSTS_COMMENT: &str = "# added by wsl2-ip-host";
     pub const WRITER_EXE: &str = "wsl2-ip-host-writer.exe";
 
-    pub fn run_wsl_ip_cmd(distro: &Option<String>) -> Result<std::process::Output, String> {
+    /// `wsl.exe [-d distro] -- <args>` without a console window
+    pub fn wsl_cmd(distro: &Option<String>, args: &[String]) -> std::process::Command {
+        wsl_cmd_as(distro, None, args)
+    }
+
+    /// `wsl_cmd` running as `user` inside the distro
+    pub fn wsl_cmd_as(
+        distro: &Option<String>,
+        user: Option<&str>,
+        args: &[String],
+    ) -> std::process::Command {
         use std::os::windows::process::CommandExt;
 
-        let mut args = if let Some(s) = distro {
-            vec!["-d", &s[..]]
-        } else {
-            vec![]
-        };
+        let mut cmd = std::process::Command::new("wsl.exe");
 
-        args.append(&mut vec![
-            "--", "ip", "-4", "-br", "address", "show", "eth0",
-        ]);
+        if let Some(s) = distro {
+            cmd.args(["-d", &s[..]]);
+        }
 
-        let mut cmd = std::process::Command::new("wsl.exe");
+        if let Some(u) = user {
+            cmd.args(["-u", u]);
+        }
 
+        cmd.arg("--");
         cmd.args(args);
         cmd.creation_flags(CREATE_NO_WINDOW);
-        cmd.output().map_err(|e| format!("{}", e))
+        cmd
+    }
+
+    #[derive(Debug)]
+    pub enum RunError {
+        Io(String),
+        Timeout(std::time::Duration),
+        Cancelled,
+    }
+
+    impl std::fmt::Display for RunError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                RunError::Io(e) => write!(f, "{}", e),
+                RunError::Timeout(t) => write!(f, "Timed out after {}s.", t.as_secs()),
+                RunError::Cancelled => write!(f, "Cancelled."),
+            }
+        }
+    }
+
+    /// runs `cmd` to completion unless it outlives `timeout` or `cancel` is set, in which
+    /// case the child is killed so a hung distro can't block the caller
+    pub fn run_with_timeout(
+        cmd: std::process::Command,
+        timeout: std::time::Duration,
+        cancel: &super::Cancel,
+    ) -> Result<std::process::Output, RunError> {
+        run_with_input(cmd, None, timeout, cancel)
+    }
+
+    /// `run_with_timeout` that feeds `input` to the child's stdin
+    pub fn run_with_input(
+        mut cmd: std::process::Command,
+        input: Option<Vec<u8>>,
+        timeout: std::time::Duration,
+        cancel: &super::Cancel,
+    ) -> Result<std::process::Output, RunError> {
+        use std::io::{Read, Write};
+        use std::process::Stdio;
+        use std::time::Instant;
+
+        let stdin = match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        };
+
+        let mut child = cmd
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::Io(format!("{}", e)))?;
+
+        // stdin is closed when the thread drops it so the child sees the end of input
+        let writer = match (child.stdin.take(), input) {
+            (Some(mut pipe), Some(input)) => {
+                Some(std::thread::spawn(move || pipe.write_all(&input)))
+            }
+            _ => None,
+        };
+
+        // drain both pipes while waiting so a chatty child can't fill them and stall
+        let mut out = child.stdout.take().unwrap();
+        let mut err = child.stderr.take().unwrap();
+        let stdout = std::thread::spawn(move || {
+            let mut buf = vec![];
+            out.read_to_end(&mut buf).map(|_| buf)
+        });
+        let stderr = std::thread::spawn(move || {
+            let mut buf = vec![];
+            err.read_to_end(&mut buf).map(|_| buf)
+        });
+
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child
+                .try_wait()
+                .map_err(|e| RunError::Io(format!("{}", e)))?
+            {
+                break status;
+            }
+
+            if cancel.is_cancelled() {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(RunError::Cancelled);
+            }
+
+            if start.elapsed() >= timeout {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(RunError::Timeout(timeout));
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(25));
+        };
+
+        if let Some(writer) = writer {
+            match writer.join() {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => return Err(RunError::Io(format!("Unable to write input: {}", e))),
+                Err(_) => return Err(RunError::Io("Unable to write input.".to_owned())),
+            };
+        }
+
+        let join = |h: std::thread::JoinHandle<std::io::Result<Vec<u8>>>| match h.join() {
+            Ok(r) => r.map_err(|e| RunError::Io(format!("{}", e))),
+            Err(_) => Err(RunError::Io("Unable to read command output.".to_owned())),
+        };
+
+        Ok(std::process::Output {
+            status,
+            stdout: join(stdout)?,
+            stderr: join(stderr)?,
+        })
+    }
+
+    /// `program` without a console window
+    pub fn hidden_cmd(program: &str) -> std::process::Command {
+        use std::os::windows::process::CommandExt;
+
+        let mut cmd = std::process::Command::new(program);
+        cmd.creation_flags(CREATE_NO_WINDOW);
+        cmd
     }
 
     pub fn run_wsl_list_distros() -> Result<std::process::Output, String> {
@@ -38,17 +242,22 @@ mod util {
         cmd.output().map_err(|e| format!("{}", e))
     }
 
-    pub fn clean_list(list: &[String]) -> Vec<String> {
-        list.to_owned()
-            .into_iter()
-            .filter_map(|line| {
-                if line.contains(HOSTS_COMMENT) {
-                    None
-                } else {
-                    Some(line)
-                }
-            })
-            .collect()
+    pub fn run_netsh(args: &[String]) -> Result<String, String> {
+        use std::os::windows::process::CommandExt;
+
+        let output = std::process::Command::new("netsh.exe")
+            .args(args)
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+            .map_err(|e| format!("{}", e))?;
+
+        // netsh reports most failures on stdout
+        let text = String::from_utf8_lossy(&output.stdout).into_owned();
+        if false == output.status.success() {
+            return Err(text.trim().to_owned());
+        }
+
+        Ok(text)
     }
 
     pub fn null_text(text: &str) -> Vec<u16> {
@@ -62,36 +271,448 @@ mod util {
 
 pub const DEFAULT_HOSTS_PATH: &str = "C:\\Windows\\System32\\drivers\\etc\\hosts";
 pub const DEFAULT_HOST: &str = "host.wsl.internal";
+pub const DEFAULT_INTERFACE: &str = "eth0";
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// shells to wsl2-ip-host-writer to build a config and write
-pub fn write_changes(ip: &str, state: &Config) -> Result<(), String> {
-    use std::ptr;
-    use winapi::ctypes::c_int;
-    use winapi::um::shellapi::ShellExecuteW;
+/// how long the writer may take, the elevation prompt included
+pub const WRITER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// runs wsl2-ip-host-writer with `args`, waiting for it to finish so a failed write is
+/// reported and nothing runs after it. waiting stops when `cancel` is set or after
+/// `WRITER_TIMEOUT`, the elevated writer can't be killed from here so it is left to
+/// finish on its own
+fn run_writer(args: &str, cancel: &Cancel) -> Result<(), String> {
+    use std::time::Instant;
+    use std::{mem, ptr};
+    use winapi::shared::winerror::WAIT_TIMEOUT;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::GetExitCodeProcess;
+    use winapi::um::shellapi::{ShellExecuteExW, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW};
+    use winapi::um::synchapi::WaitForSingleObject;
+
+    // the writer logs to its own file, at the same level as us
+    let args = match log::log_enabled!(log::Level::Debug) {
+        true => format!("{} --verbose", args),
+        false => args.to_owned(),
+    };
+    log::debug!("running {} {}", WRITER_EXE, args);
 
     let verb: Vec<u16> = util::null_text("open");
-    let names = state.names.join(",");
-    let path = &state.hosts_path;
     let file = util::null_text(WRITER_EXE);
-    let args = util::null_text(format!("{} {} {}", ip, names, path).as_str());
-
-    let ret = unsafe {
-        ShellExecuteW(
-            ptr::null_mut(),
-            verb.as_ptr(),
-            file.as_ptr(),
-            args.as_ptr(),
-            ptr::null(),
-            c_int::from(0),
-        )
+    let args = util::null_text(&args);
+
+    let mut info: SHELLEXECUTEINFOW = unsafe { mem::zeroed() };
+    info.cbSize = mem::size_of::<SHELLEXECUTEINFOW>() as u32;
+    info.fMask = SEE_MASK_NOCLOSEPROCESS;
+    info.lpVerb = verb.as_ptr();
+    info.lpFile = file.as_ptr();
+    info.lpParameters = args.as_ptr();
+    info.lpDirectory = ptr::null();
+    info.nShow = 0;
+
+    let started = unsafe { ShellExecuteExW(&mut info) };
+    if started == 0 || info.hProcess.is_null() {
+        return Err("Unable to run wsl2-ip-host-writer.".to_owned());
+    }
+
+    let start = Instant::now();
+    let waited = loop {
+        if unsafe { WaitForSingleObject(info.hProcess, 100) } != WAIT_TIMEOUT {
+            break Ok(());
+        }
+        if cancel.is_cancelled() {
+            break Err("Stopped waiting for wsl2-ip-host-writer.".to_owned());
+        }
+        if start.elapsed() >= WRITER_TIMEOUT {
+            break Err(format!(
+                "wsl2-ip-host-writer didn't finish within {}s.",
+                WRITER_TIMEOUT.as_secs()
+            ));
+        }
     };
 
-    if ret as i32 > 31 {
+    let mut code = 0;
+    unsafe {
+        if waited.is_ok() {
+            GetExitCodeProcess(info.hProcess, &mut code);
+        }
+        CloseHandle(info.hProcess);
+    }
+    waited?;
+
+    if code == 0 {
         Ok(())
     } else {
-        Err("Unable to run wsl2-ip-host-writer.".to_owned())
+        Err(format!(
+            "wsl2-ip-host-writer failed with exit code {}.",
+            code
+        ))
+    }
+}
+
+/// shells to wsl2-ip-host-writer to build a config and write
+pub fn write_changes(
+    update: &target::Update,
+    state: &Config,
+    cancel: &Cancel,
+) -> Result<(), String> {
+    // a lone comma stands for no names when every name has another source, an empty
+    // argument would be lost when the writer's command line is split
+    let names = match update.names.is_empty() {
+        true => ",".to_owned(),
+        false => update.names.join(","),
+    };
+    let mut args = format!("{} {} {}", update.ip, names, state.hosts_path);
+    for mapping in update.others.iter() {
+        args.push_str(&format!(" --map {}", mapping));
+    }
+    for forward in state.port_forwards.iter() {
+        args.push_str(&format!(" --forward {}", forward));
+    }
+    if state.on_conflict != OnConflict::default() {
+        args.push_str(&format!(" --on-conflict {}", state.on_conflict));
     }
+    if state.line_style != LineStyle::default() {
+        args.push_str(&format!(" --line-style {}", state.line_style));
+    }
+    for entry in state.static_entries.iter() {
+        args.push_str(&format!(" --static {}", entry));
+    }
+
+    run_writer(&args, cancel)
+}
+
+/// shells to wsl2-ip-host-writer to take our lines out of the hosts file and delete the
+/// port forwards we added
+pub fn remove_changes(state: &Config, cancel: &Cancel) -> Result<(), String> {
+    run_writer(&format!("--remove {}", state.hosts_path), cancel)
+}
+
+/// each target's name and plan, and the names left out of them
+pub type Preview = (Vec<(String, target::Plan)>, Vec<String>);
+
+/// what each target would write with the distro at `ip`, without writing anything, and
+/// the names that were left out since their source couldn't be looked up
+pub fn preview_targets(discovery: &Discovery, ip: &str, state: &Config) -> Result<Preview, String> {
+    let (update, unresolved) = source::resolve(discovery, state, ip)?;
+
+    let plans = state
+        .targets()
+        .iter()
+        .map(|t| match t.plan(&update) {
+            Ok(plan) => Ok((t.describe(), plan)),
+            Err(e) => Err(format!("{}: {}", t.describe(), e)),
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok((plans, unresolved))
+}
+
+/// writes `ip` to every target that changes. the ones only the elevated writer can
+/// change are left to it, along with the port forwards, so there is a single prompt and
+/// none at all when nothing needing elevation has changed. returns the targets that
+/// were written
+pub fn write_targets(
+    update: &target::Update,
+    state: &Config,
+    cancel: &Cancel,
+) -> Result<Vec<String>, String> {
+    state.check()?;
+    let ip = &update.ip;
+    let forwards = false == plan_port_forwards(ip, &state.port_forwards)?.is_empty();
+    let mut elevate = forwards;
+    let mut written = vec![];
+
+    for t in state.targets() {
+        let needs_elevation = t.needs_elevation();
+        let changed = match needs_elevation {
+            true => t.plan(update).map(|plan| plan.changed),
+            false => t.write(update),
+        }
+        .map_err(|e| format!("{}: {}", t.describe(), e))?;
+
+        if false == changed {
+            log::debug!("{} is up to date", t.describe());
+            continue;
+        } else if needs_elevation {
+            log::debug!("{} needs the writer", t.describe());
+            elevate = true;
+        } else {
+            log::info!("wrote {} to {}", ip, t.describe());
+        }
+        written.push(t.describe());
+    }
+
+    if elevate {
+        write_changes(update, state, cancel)?;
+    }
+    if forwards {
+        written.push("port forwards".to_owned());
+    }
+
+    Ok(written)
+}
+
+/// takes everything we wrote out of every target, and the port forwards we added
+pub fn remove_targets(state: &Config, cancel: &Cancel) -> Result<(), String> {
+    let mut elevate = false == plan_port_forward_removal()?.is_empty();
+
+    for t in state.targets() {
+        match t.needs_elevation() {
+            true => elevate = true,
+            false => t.remove().map_err(|e| format!("{}: {}", t.describe(), e))?,
+        };
+    }
+
+    match elevate {
+        true => remove_changes(state, cancel),
+        false => Ok(()),
+    }
+}
+
+/// the outcome of a successful `sync`
+#[derive(Debug)]
+pub struct Synced {
+    /// what discovery found, `None` when the write was skipped without asking the distro
+    pub found: Option<Found>,
+    /// `networkingMode` from `.wslconfig`
+    pub mode: wslconfig::Mode,
+    /// what the names were pointed at, 127.0.0.1 in mirrored mode
+    pub address: String,
+    /// nothing was written since the distro shares windows' addresses
+    pub skipped: bool,
+    /// the address our hosts lines held before
+    pub old_ip: Option<String>,
+    /// the targets that were written, empty when everything was up to date
+    pub written: Vec<String>,
+    /// lines we don't manage that map one of our names, dealt with as `on_conflict` says
+    pub conflicts: Vec<String>,
+    /// names left out since their source couldn't be looked up
+    pub unresolved: Vec<String>,
+    pub outcomes: Vec<hooks::Outcome>,
+    /// set when the write went through but couldn't be recorded
+    pub journal_error: Option<String>,
+}
+
+impl Synced {
+    pub fn ip(&self) -> &str {
+        &self.address
+    }
+
+    /// a skipped write, names left out, failed hooks and journal trouble, for the caller
+    /// to report
+    pub fn warnings(&self) -> Vec<String> {
+        let mut list = vec![];
+        if self.skipped {
+            list.push("WSL uses mirrored networking, so nothing was written.".to_owned());
+        }
+        list.extend(self.conflicts.iter().cloned());
+        list.extend(self.unresolved.iter().cloned());
+        list.extend(self.outcomes.iter().filter_map(|o| o.warning()));
+        if let Some(e) = &self.journal_error {
+            list.push(format!("Unable to record the write in the journal: {}", e));
+        }
+        list
+    }
+}
+
+/// finds the distro's address, writes it to every target and runs the hooks, which are
+/// told the address our lines held before. in mirrored networking mode the names point
+/// at 127.0.0.1 or nothing is written, as `mirrored` says. the result goes in `journal`
+/// when there is one, whether it succeeded or not
+pub fn sync(
+    discovery: &Discovery,
+    state: &Config,
+    journal: Option<&journal::Journal>,
+) -> Result<Synced, String> {
+    let old_ip = state.current_ip();
+    let mut entry = journal::Entry::new(state.distro.to_owned(), old_ip.to_owned());
+    let mode = wslconfig::read_mode().unwrap_or_else(|e| {
+        log::warn!("Unable to read {}: {}", wslconfig::WSLCONFIG_NAME, e);
+        wslconfig::Mode::default()
+    });
+
+    let mirrored = mode == wslconfig::Mode::Mirrored;
+    if mirrored && false == state.port_forwards.is_empty() {
+        log::info!("leaving port forwards alone in mirrored mode");
+    }
+
+    let result = if mirrored && state.mirrored == OnMirrored::Skip {
+        // nothing would be written, so there's no need to ask the distro either
+        Ok(Synced {
+            found: None,
+            mode: mode.to_owned(),
+            address: wslconfig::LOOPBACK_IP.to_owned(),
+            skipped: true,
+            old_ip: old_ip.to_owned(),
+            written: vec![],
+            conflicts: vec![],
+            unresolved: vec![],
+            outcomes: vec![],
+            journal_error: None,
+        })
+    } else {
+        discovery
+            .find_with_strategy(&state.distro)
+            .and_then(|found| {
+                let ip = match mirrored {
+                    true => wslconfig::LOOPBACK_IP.to_owned(),
+                    false => found.ips[0].to_owned(),
+                };
+                entry.strategy = Some(found.strategy.to_string());
+                entry.new_ip = Some(ip.to_owned());
+
+                let (update, unresolved) = source::resolve(discovery, state, &ip)?;
+                let conflicts: Vec<String> = state
+                    .hosts_file()
+                    .conflicts(&update)?
+                    .iter()
+                    .map(|c| match state.on_conflict {
+                        OnConflict::Warn => format!("{} and may be used instead of ours.", c),
+                        OnConflict::Skip => format!("{}, so ours was left out.", c),
+                        OnConflict::CommentOut => format!("{}, so it was commented out.", c),
+                    })
+                    .collect();
+                conflicts.iter().for_each(|c| log::warn!("{}", c));
+
+                let written = write_targets(&update, state, &discovery.cancel)?;
+                entry.targets = written.to_owned();
+
+                let event = hooks::Event::new(state, old_ip.to_owned(), &ip);
+                let outcomes = hooks::run_all(&state.hooks, &event, &discovery.cancel);
+
+                Ok(Synced {
+                    found: Some(found),
+                    mode: mode.to_owned(),
+                    address: ip,
+                    skipped: false,
+                    old_ip: old_ip.to_owned(),
+                    written,
+                    conflicts,
+                    unresolved,
+                    outcomes,
+                    journal_error: None,
+                })
+            })
+    };
+
+    entry.decision = match &result {
+        Ok(synced) if synced.skipped => journal::Decision::Skipped,
+        Ok(synced) if synced.written.is_empty() => journal::Decision::Unchanged,
+        Ok(_) => journal::Decision::Wrote,
+        Err(_) => journal::Decision::Failed,
+    };
+    entry.error = result.as_ref().err().cloned();
+
+    match &result {
+        Ok(synced) => log::info!(
+            "{} {} for {}, found with {} in {} mode",
+            entry.decision,
+            synced.ip(),
+            discovery::label(&state.distro),
+            synced
+                .found
+                .as_ref()
+                .map_or("nothing".to_owned(), |f| f.strategy.to_string()),
+            synced.mode
+        ),
+        Err(e) => log::error!("{}", e),
+    };
+
+    let recorded = match journal {
+        Some(j) => j.append(&entry),
+        None => Ok(()),
+    };
+    if let Err(e) = &recorded {
+        log::warn!("Unable to record in the journal: {}", e);
+    }
+
+    match (result, recorded) {
+        (Ok(mut synced), recorded) => {
+            synced.journal_error = recorded.err();
+            Ok(synced)
+        }
+        (Err(e), Ok(())) => Err(e),
+        (Err(e), Err(j)) => Err(format!(
+            "{}\nUnable to record the failure in the journal: {}",
+            e, j
+        )),
+    }
+}
+
+/// the netsh portproxy changes that would point `forwards` at `ip` and delete the rules
+/// of forwards we added before that are no longer configured, listing the rules doesn't
+/// need elevation. nothing changes when `ip` is loopback, as in mirrored mode, since
+/// forwards to it would take the very ports the distro serves on
+pub fn plan_port_forwards(
+    ip: &str,
+    forwards: &[PortForward],
+) -> Result<Vec<portproxy::Change>, String> {
+    if ip == wslconfig::LOOPBACK_IP {
+        return Ok(vec![]);
+    }
+
+    let managed = portproxy::read_managed(&portproxy::managed_path()?)?;
+    if forwards.is_empty() && managed.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let dumped = util::run_netsh(&portproxy::dump_args())?;
+    Ok(portproxy::plan(
+        forwards,
+        ip,
+        &portproxy::parse_dump(&dumped),
+        &managed,
+    ))
+}
+
+/// brings the netsh portproxy rules in line with `forwards` pointing at `ip`, this
+/// needs elevation so it runs in the writer. returns the changes that were made
+pub fn apply_port_forwards(
+    ip: &str,
+    forwards: &[PortForward],
+) -> Result<Vec<portproxy::Change>, String> {
+    if ip == wslconfig::LOOPBACK_IP {
+        return Ok(vec![]);
+    }
+
+    let changes = plan_port_forwards(ip, forwards)?;
+    apply_changes(&changes)?;
+    portproxy::save_managed(&portproxy::managed_path()?, forwards)?;
+
+    Ok(changes)
+}
+
+/// the deletes that take out every rule we added
+pub fn plan_port_forward_removal() -> Result<Vec<portproxy::Change>, String> {
+    let managed = portproxy::read_managed(&portproxy::managed_path()?)?;
+    if managed.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let dumped = util::run_netsh(&portproxy::dump_args())?;
+    Ok(portproxy::removal(
+        &managed,
+        &portproxy::parse_dump(&dumped),
+    ))
+}
+
+/// deletes every rule we added, this needs elevation so it runs in the writer. returns
+/// the changes that were made
+pub fn remove_port_forwards() -> Result<Vec<portproxy::Change>, String> {
+    let changes = plan_port_forward_removal()?;
+    apply_changes(&changes)?;
+    portproxy::save_managed(&portproxy::managed_path()?, &[])?;
+
+    Ok(changes)
+}
+
+fn apply_changes(changes: &[portproxy::Change]) -> Result<(), String> {
+    changes.iter().try_for_each(|change| {
+        util::run_netsh(&change.args())
+            .map(|_| ())
+            .map_err(|e| format!("Unable to {}: {}", change, e))
+    })
 }
 
 pub fn find_writer() -> Option<String> {
@@ -105,25 +726,59 @@ pub fn find_writer() -> Option<String> {
     }
 }
 
-pub fn find_wsl_ip(distro: &Option<String>) -> Result<String, String> {
-    let output = util::run_wsl_ip_cmd(distro)?;
-    if false == output.status.success() {
-        return Err("Unable to run ip command.".to_owned());
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    pub(crate) fn flag(&self) -> &'static str {
+        match self {
+            Family::V4 => "-4",
+            Family::V6 => "-6",
+        }
+    }
+}
+
+/// which interface and address family to ask `ip` about
+#[derive(Clone, Debug)]
+pub struct IpQuery {
+    pub interface: String,
+    pub family: Family,
+}
+
+impl IpQuery {
+    pub fn new() -> IpQuery {
+        IpQuery {
+            interface: DEFAULT_INTERFACE.to_owned(),
+            family: Family::V4,
+        }
     }
+}
 
-    let txt = String::from_utf8(output.stdout).map_err(|e| format!("{}", e))?;
+impl Default for IpQuery {
+    fn default() -> IpQuery {
+        IpQuery::new()
+    }
+}
 
-    let ip = match txt.split_whitespace().last() {
-        Some(text) => Ok(text.to_owned()),
-        None => Err("Unable to split output text.".to_owned()),
-    }?;
+pub fn find_wsl_ip(distro: &Option<String>) -> Result<String, String> {
+    let ips = find_wsl_ips(distro, &IpQuery::new())?;
 
-    match ip.split("/").next() {
-        Some(ip) => Ok(ip.to_owned()),
-        None => Err("Unable to separate IP from subnet".to_owned()),
+    match ips.into_iter().next() {
+        Some(ip) => Ok(ip),
+        None => Err("Unable to find an IP in the ip command output.".to_owned()),
     }
 }
 
+/// all addresses of the queried interface and family, in the order `ip` lists them
+pub fn find_wsl_ips(distro: &Option<String>, query: &IpQuery) -> Result<Vec<String>, String> {
+    let mut discovery = Discovery::new();
+    discovery.query = query.clone();
+    discovery.find(distro)
+}
+
 pub fn find_wsl_distros() -> Result<Vec<String>, String> {
     let output = util::run_wsl_list_distros()?;
     if false == output.status.success() {
@@ -131,23 +786,44 @@ pub fn find_wsl_distros() -> Result<Vec<String>, String> {
             .unwrap_or("Unable to get a list of distros from wsl.exe.".to_owned()));
     }
 
-    // wsl.exe outputs utf16 so convert the output to a [u16] from a [u8]
-    let b: Vec<u16> = output
-        .stdout
-        .chunks_exact(2)
-        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
-        .collect();
-
-    let txt = String::from_utf16(&b).map_err(|e| format!("{}", e))?;
+    let txt = distro::decode(&output.stdout)?;
 
     Ok(txt.lines().skip(1).map(|l| l.trim().to_owned()).collect())
 }
 
+/// strips the `(Default)` marker `wsl.exe -l` puts next to the default distro
+pub fn distro_name(line: &str) -> String {
+    line.replace("(Default)", "").trim().to_owned()
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub hosts_path: String,
     pub names: Vec<String>,
+    /// labels a `*.domain` entry in `names` expands to in the hosts file
+    pub subdomains: Vec<String>,
     pub distro: Option<String>,
+    /// netsh portproxy rules kept pointing at the distro
+    pub port_forwards: Vec<PortForward>,
+    pub strategies: Vec<Strategy>,
+    /// programs run after each successful write
+    pub hooks: Vec<Hook>,
+    /// `Host` entries kept in an ssh config alongside the hosts file
+    pub ssh: Option<SshConfig>,
+    /// other files the ip is written to
+    pub outputs: Vec<Output>,
+    /// what to do about our names on lines we don't manage
+    pub on_conflict: OnConflict,
+    /// one line per name or several names to a line
+    pub line_style: LineStyle,
+    /// fixed lines kept with ours in the hosts file, e.g. `127.0.0.1 local.myapp.test`
+    pub static_entries: Vec<Mapping>,
+    /// where names that don't point at the distro get their address
+    pub sources: Vec<NameSource>,
+    /// what to do when wsl uses mirrored networking
+    pub mirrored: OnMirrored,
+    /// whether discovery starts the distro when it is stopped
+    pub on_stopped: OnStopped,
 }
 
 pub struct Access {
@@ -198,7 +874,19 @@ impl Config {
         Config {
             hosts_path: path.to_owned(),
             names: vec![],
+            subdomains: vec![],
             distro: None,
+            port_forwards: vec![],
+            strategies: Strategy::ALL.to_vec(),
+            hooks: vec![],
+            ssh: None,
+            outputs: vec![],
+            on_conflict: OnConflict::default(),
+            line_style: LineStyle::default(),
+            static_entries: vec![],
+            sources: vec![],
+            mirrored: OnMirrored::default(),
+            on_stopped: OnStopped::default(),
         }
     }
 
@@ -209,28 +897,88 @@ impl Config {
             return Err(format!("Unable to read file {}", self.hosts_path));
         }
 
-        let file = std::fs::File::open(access.path).map_err(|e| format!("{}", e))?;
-        let reader = std::io::BufReader::new(file);
+        let text = target::read(&access.path)?;
+        Ok(target::split(&text))
+    }
 
-        Ok(reader.lines().filter_map(|s| s.ok()).collect())
+    /// the address our lines in the hosts file point at, if there are any
+    pub fn current_ip(&self) -> Option<String> {
+        self.read_file()
+            .ok()
+            .and_then(|lines| hosts::managed_ip(&lines))
     }
 
-    pub fn apply_names(&self, ip: &str, lines: &[String]) -> Vec<String> {
-        let mut list = lines.to_owned();
+    /// our lines in the hosts file, other lines mapping our names and other programs'
+    /// blocks
+    pub fn status(&self) -> Result<hosts::Status, String> {
+        Ok(hosts::status(&self.expanded_names(), &self.read_file()?))
+    }
+
+    /// `names` with wildcards replaced by a name per subdomain
+    pub fn expanded_names(&self) -> Vec<String> {
+        names::expand(&self.names, &self.subdomains)
+    }
+
+    /// every name, source and static entry is one the hosts file can hold, and no name is
+    /// both a host name and a static entry
+    pub fn check(&self) -> Result<(), String> {
+        let names = self.expanded_names();
+        for name in names.iter() {
+            names::check(name)?;
+        }
+        for source in self.sources.iter() {
+            names::check(&source.name)?;
+        }
+
+        for forward in self.port_forwards.iter() {
+            forward.check()?;
+        }
+        if let Some(ssh) = &self.ssh {
+            ssh.check()?;
+        }
 
-        list.extend(
-            self.names
+        for entry in self.static_entries.iter() {
+            entry.check()?;
+            if let Some(name) = entry
+                .names
                 .iter()
-                .map(|name| format!("{} {} {}", &ip, name, util::HOSTS_COMMENT)),
-        );
+                .find(|n| names.iter().any(|d| d.eq_ignore_ascii_case(n)))
+            {
+                return Err(format!("{} is both a host name and a static entry.", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn apply_names(&self, ip: &str, lines: &[String]) -> Vec<String> {
+        hosts::apply(ip, &self.expanded_names(), lines, self.line_style)
+    }
+
+    pub fn hosts_file(&self) -> target::HostsFile {
+        target::HostsFile {
+            path: self.hosts_path.to_owned(),
+            on_conflict: self.on_conflict,
+            line_style: self.line_style,
+            static_entries: self.static_entries.to_owned(),
+        }
+    }
+
+    /// every place the ip is written, the hosts file first
+    pub fn targets(&self) -> Vec<Box<dyn Target>> {
+        let mut list: Vec<Box<dyn Target>> = vec![Box::new(self.hosts_file())];
+
+        if let Some(ssh) = &self.ssh {
+            list.push(Box::new(ssh.clone()));
+        }
+        list.extend(self.outputs.iter().map(|o| o.target()));
 
         list
     }
 
     pub fn preview(&self, ip: &str) -> Result<Vec<String>, String> {
-        let lines = self.read_file()?;
-        let lines = util::clean_list(&lines);
-        Ok(self.apply_names(ip, &lines))
+        let plan = self.hosts_file().plan(&target::Update::new(self, ip))?;
+        Ok(plan.lines())
     }
 
     pub fn write_file(&self, ip: &str) -> Result<(), String> {
@@ -243,23 +991,45 @@ impl Config {
             ));
         }
 
-        let lines = self.preview(ip)?;
-
-        use std::io::Write;
-        let mut file = std::fs::File::create(access.path).map_err(|e| format!("{}", e))?;
-
-        lines.iter().for_each(|l| {
-            writeln!(&mut file, "{}", l).unwrap();
-        });
-
-        Ok(())
+        self.hosts_file()
+            .write(&target::Update::new(self, ip))
+            .map(|_| ())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn strips_default_marker_from_distro() {
+        assert_eq!(distro_name("Ubuntu (Default)"), "Ubuntu");
+        assert_eq!(distro_name("  Debian "), "Debian");
+    }
+
+    #[test]
+    fn setting_names_match_serde() {
+        fn check<T>(all: &[T], unknown: &str)
+        where
+            T: serde::Serialize + std::str::FromStr<Err = String> + std::fmt::Display,
+            T: Copy + PartialEq + std::fmt::Debug,
+        {
+            for value in all {
+                let json = serde_json::to_string(value).unwrap();
+                assert_eq!(json, format!("\"{}\"", value));
+                assert_eq!(value.to_string().parse::<T>(), Ok(*value));
+            }
+            assert!(unknown.parse::<T>().unwrap_err().starts_with("Unknown "));
+        }
+
+        check(OnStopped::ALL, "wait");
+        check(OnConflict::ALL, "comment_out");
+        check(LineStyle::ALL, "one-line");
+        check(OnMirrored::ALL, "nat");
+    }
 }
@@ -0,0 +1,232 @@
+use crate::journal::format_time;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// folder in the user's home holding each program's log
+pub const LOG_DIR: &str = ".wsl2-ip-host-logs";
+/// size a log may reach before it is rotated
+pub const MAX_BYTES: u64 = 1024 * 1024;
+/// rotated logs kept next to the current one, as `<name>.log.1` and so on
+pub const KEEP: usize = 3;
+
+/// how a program wants to log, filled in from `--verbose` and `--log-file`
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// debug messages as well as info and above
+    pub verbose: bool,
+    /// `~/.wsl2-ip-host-logs/<name>.log` when not set
+    pub path: Option<PathBuf>,
+    /// also print to stderr, for the cli
+    pub stderr: bool,
+}
+
+impl Options {
+    /// takes `--verbose` and `--log-file <path>` out of `args`, leaving the rest for the
+    /// program to parse
+    pub fn from_args(args: &mut Vec<String>) -> Options {
+        let mut options = Options::default();
+        let mut rest = vec![];
+        let mut iter = args.drain(..);
+
+        while let Some(arg) = iter.next() {
+            match &arg[..] {
+                "-v" | "--verbose" => options.verbose = true,
+                "--log-file" => options.path = iter.next().map(PathBuf::from),
+                _ => rest.push(arg),
+            };
+        }
+
+        drop(iter);
+        *args = rest;
+        options
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        match self.verbose {
+            true => LevelFilter::Debug,
+            false => LevelFilter::Info,
+        }
+    }
+}
+
+pub fn default_path(name: &str) -> Result<PathBuf, String> {
+    match home::home_dir() {
+        Some(p) => Ok(p.join(LOG_DIR).join(format!("{}.log", name))),
+        None => Err("Unable to locate home folder.".to_owned()),
+    }
+}
+
+/// `path` with `.n` added, `.0` being the log itself
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    match n {
+        0 => path.to_owned(),
+        _ => PathBuf::from(format!("{}.{}", path.display(), n)),
+    }
+}
+
+/// moves `<name>.log` to `<name>.log.1` and each older log up one, dropping the oldest
+pub fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    let oldest = rotated(path, keep);
+    if oldest.exists() {
+        std::fs::remove_file(&oldest)?;
+    }
+
+    for n in (0..keep).rev() {
+        let from = rotated(path, n);
+        if from.exists() {
+            std::fs::rename(&from, rotated(path, n + 1))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// one line per message, e.g. `2024-02-29 12:34:56 INFO  wsl2-ip-host-writer[42] wrote ...`
+pub fn format_line(time: u64, name: &str, level: Level, message: &str) -> String {
+    format!(
+        "{} {:<5} {}[{}] {}\n",
+        format_time(time),
+        level,
+        name,
+        std::process::id(),
+        message
+    )
+}
+
+struct FileLogger {
+    name: String,
+    path: PathBuf,
+    level: LevelFilter,
+    stderr: bool,
+    file: Mutex<Option<File>>,
+}
+
+impl FileLogger {
+    fn open(&self) -> std::io::Result<File> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+    }
+
+    fn write(&self, line: &str) -> std::io::Result<()> {
+        let mut file = match self.file.lock() {
+            Ok(f) => f,
+            Err(e) => e.into_inner(),
+        };
+
+        let full = match &*file {
+            Some(f) => f.metadata()?.len() > MAX_BYTES,
+            None => false,
+        };
+        if full {
+            *file = None;
+            rotate(&self.path, KEEP)?;
+        }
+
+        if file.is_none() {
+            *file = Some(self.open()?);
+        }
+
+        file.as_mut().unwrap().write_all(line.as_bytes())
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if false == self.enabled(record.metadata()) {
+            return;
+        }
+
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let line = format_line(time, &self.name, record.level(), &record.args().to_string());
+
+        if self.stderr {
+            eprint!("{}", line);
+        }
+        // there's nowhere left to report a log that can't be written
+        let _ = self.write(&line);
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(f) = file.as_mut() {
+                let _ = f.flush();
+            }
+        }
+    }
+}
+
+/// sends the `log` macros to `name`'s log file for the rest of the run. returns where
+/// the log is going
+pub fn init(name: &str, options: &Options) -> Result<PathBuf, String> {
+    let path = match &options.path {
+        Some(p) => p.to_owned(),
+        None => default_path(name)?,
+    };
+
+    let logger = FileLogger {
+        name: name.to_owned(),
+        path: path.to_owned(),
+        level: options.level(),
+        stderr: options.stderr,
+        file: Mutex::new(None),
+    };
+
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| format!("{}", e))?;
+    log::set_max_level(options.level());
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_logging_options_out_of_args() {
+        let mut args: Vec<String> = ["-d", "Ubuntu", "--verbose", "--log-file", "a.log", "ip"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let options = Options::from_args(&mut args);
+
+        assert!(options.verbose);
+        assert_eq!(options.path, Some(PathBuf::from("a.log")));
+        assert_eq!(args, vec!["-d", "Ubuntu", "ip"]);
+        assert_eq!(Options::default().level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn rotates_oldest_out() {
+        let dir = std::env::temp_dir().join(format!("wsl2-ip-host-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.log");
+
+        for n in 0..3 {
+            std::fs::write(&path, n.to_string()).unwrap();
+            rotate(&path, 2).unwrap();
+        }
+
+        let read = |n| std::fs::read_to_string(rotated(&path, n)).ok();
+        assert_eq!(read(0), None);
+        assert_eq!(read(1), Some("2".to_owned()));
+        assert_eq!(read(2), Some("1".to_owned()));
+        assert_eq!(read(3), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+/// `*.myapp.wsl.internal` stands for any name ending in `.myapp.wsl.internal`
+pub fn is_wildcard(name: &str) -> bool {
+    name.starts_with("*.")
+}
+
+/// hosts files can't hold wildcards so each one becomes a name per subdomain, plain
+/// names pass through. a wildcard with no subdomains configured expands to nothing
+pub fn expand(names: &[String], subdomains: &[String]) -> Vec<String> {
+    let mut list: Vec<String> = vec![];
+
+    for name in names {
+        let expanded = match name.strip_prefix("*.") {
+            Some(suffix) => subdomains
+                .iter()
+                .map(|sub| format!("{}.{}", sub.trim_matches('.'), suffix))
+                .collect(),
+            None => vec![name.to_owned()],
+        };
+
+        for name in expanded {
+            if false == list.contains(&name) {
+                list.push(name);
+            }
+        }
+    }
+
+    list
+}
+
+/// a name the hosts file can hold: dot separated labels of letters, digits, `-` and `_`,
+/// with `*.` in front for a wildcard
+pub fn check(name: &str) -> Result<(), String> {
+    let host = name.strip_prefix("*.").unwrap_or(name);
+    let label = |l: &str| {
+        (1..=63).contains(&l.len())
+            && false == l.starts_with('-')
+            && false == l.ends_with('-')
+            && l.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+
+    match host.len() <= 253 && host.split('.').all(label) {
+        true => Ok(()),
+        false => Err(format!("{} is not a valid host name.", name)),
+    }
+}
+
+/// whether `name` is `pattern` or, for a wildcard, any name below it. case is ignored
+/// and a trailing dot on `name` is allowed
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+
+    match pattern.strip_prefix('*') {
+        Some(suffix) => name.len() > suffix.len() && name.ends_with(suffix),
+        None => name == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn expands_wildcards_per_subdomain() {
+        assert_eq!(
+            expand(
+                &list(&["host.wsl.internal", "*.myapp.wsl.internal"]),
+                &list(&["api", "web"])
+            ),
+            list(&[
+                "host.wsl.internal",
+                "api.myapp.wsl.internal",
+                "web.myapp.wsl.internal"
+            ])
+        );
+        assert_eq!(
+            expand(&list(&["*.myapp.wsl.internal"]), &[]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn checks_host_names() {
+        assert!(check("host.wsl.internal").is_ok());
+        assert!(check("*.myapp.wsl.internal").is_ok());
+        assert!(check("my_app-1.test").is_ok());
+        assert!(check("").is_err());
+        assert!(check("a..b").is_err());
+        assert!(check("-a.test").is_err());
+        assert!(check("a.*.test").is_err());
+        assert!(check("a.test\r\n0.0.0.0 evil.test").is_err());
+        assert!(check(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn matches_names_below_wildcard() {
+        assert!(matches("*.myapp.wsl.internal", "api.myapp.wsl.internal"));
+        assert!(matches("*.myapp.wsl.internal", "a.b.MyApp.wsl.internal."));
+        assert!(!matches("*.myapp.wsl.internal", "myapp.wsl.internal"));
+        assert!(!matches("*.myapp.wsl.internal", "evilmyapp.wsl.internal"));
+        assert!(matches("host.wsl.internal", "HOST.wsl.internal."));
+        assert!(!matches("host.wsl.internal", "api.host.wsl.internal"));
+    }
+}
@@ -0,0 +1,382 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0";
+/// file name, in the user's home folder, of the forwards whose rules we added
+pub const MANAGED_NAME: &str = ".wsl2-ip-host.forwards.json";
+
+/// a windows port forwarded to the same or another port in the distro
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PortForward {
+    pub listen_address: String,
+    pub listen_port: u16,
+    pub connect_port: u16,
+}
+
+impl PortForward {
+    /// the listen address is an ipv4 address, as `v4tov4` rules need and the writer's
+    /// command line can carry
+    pub fn check(&self) -> Result<(), String> {
+        match self.listen_address.parse::<std::net::Ipv4Addr>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!(
+                "Invalid listen address {} in forward {}, expected an ipv4 address",
+                self.listen_address, self
+            )),
+        }
+    }
+
+    /// whether `rule` listens where this forward does
+    fn listens_as(&self, rule: &Rule) -> bool {
+        rule.listen_address == self.listen_address && rule.listen_port == self.listen_port
+    }
+
+    /// the rule this forward needs when the distro is at `ip`
+    pub fn rule(&self, ip: &str) -> Rule {
+        Rule {
+            listen_address: self.listen_address.to_owned(),
+            listen_port: self.listen_port,
+            connect_address: ip.to_owned(),
+            connect_port: self.connect_port,
+        }
+    }
+}
+
+/// `port`, `port:connect_port` or `address:port:connect_port`, the form used on the
+/// command line and when handing forwards to the writer
+impl std::str::FromStr for PortForward {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PortForward, String> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let port = |p: &str| {
+            p.parse::<u16>()
+                .map_err(|_| format!("Invalid port {} in forward {}", p, s))
+        };
+
+        let (address, listen, connect) = match parts[..] {
+            [listen] => (DEFAULT_LISTEN_ADDRESS, listen, listen),
+            [listen, connect] => (DEFAULT_LISTEN_ADDRESS, listen, connect),
+            [address, listen, connect] => (address, listen, connect),
+            _ => return Err(format!("Invalid forward {}", s)),
+        };
+
+        let forward = PortForward {
+            listen_address: address.to_owned(),
+            listen_port: port(listen)?,
+            connect_port: port(connect)?,
+        };
+        forward.check()?;
+
+        Ok(forward)
+    }
+}
+
+impl std::fmt::Display for PortForward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.listen_address, self.listen_port, self.connect_port
+        )
+    }
+}
+
+/// a v4tov4 rule as netsh reports it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub listen_address: String,
+    pub listen_port: u16,
+    pub connect_address: String,
+    pub connect_port: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Add(Rule),
+    Delete(Rule),
+}
+
+impl Change {
+    /// arguments to `netsh.exe` that make this change
+    pub fn args(&self) -> Vec<String> {
+        let (verb, rule) = match self {
+            Change::Add(rule) => ("add", rule),
+            Change::Delete(rule) => ("delete", rule),
+        };
+
+        let mut args = vec![
+            "interface".to_owned(),
+            "portproxy".to_owned(),
+            verb.to_owned(),
+            "v4tov4".to_owned(),
+            format!("listenaddress={}", rule.listen_address),
+            format!("listenport={}", rule.listen_port),
+        ];
+
+        if let Change::Add(_) = self {
+            args.push(format!("connectaddress={}", rule.connect_address));
+            args.push(format!("connectport={}", rule.connect_port));
+        }
+
+        args
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Add(r) => write!(
+                f,
+                "forward {}:{} -> {}:{}",
+                r.listen_address, r.listen_port, r.connect_address, r.connect_port
+            ),
+            Change::Delete(r) => write!(f, "remove {}:{}", r.listen_address, r.listen_port),
+        }
+    }
+}
+
+/// arguments to `netsh.exe` that list the existing rules as the commands that would
+/// add them, which unlike `show all` aren't translated
+pub fn dump_args() -> Vec<String> {
+    vec!["interface", "portproxy", "dump"]
+        .into_iter()
+        .map(|a| a.to_owned())
+        .collect()
+}
+
+/// reads the v4tov4 rules out of `netsh interface portproxy dump`, where each rule is an
+/// `add v4tov4 listenport=.. connectaddress=..` line. a rule without a listen address
+/// listens everywhere, which netsh shows as `*`
+pub fn parse_dump(text: &str) -> Vec<Rule> {
+    text.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            if (words.next(), words.next()) != (Some("add"), Some("v4tov4")) {
+                return None;
+            }
+
+            let fields: Vec<(String, &str)> = words
+                .filter_map(|w| w.split_once('='))
+                .map(|(k, v)| (k.to_ascii_lowercase(), v))
+                .collect();
+            let field = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, v)| *v);
+
+            Some(Rule {
+                listen_address: field("listenaddress").unwrap_or("*").to_owned(),
+                listen_port: field("listenport")?.parse().ok()?,
+                connect_address: field("connectaddress")?.to_owned(),
+                connect_port: field("connectport")?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// the changes that bring `existing` in line with `forwards` pointing at `ip`, and that
+/// delete the rules of `managed` forwards, the ones we added before, that are no longer
+/// configured. rules listening anywhere else are left alone since they aren't ours
+pub fn plan(
+    forwards: &[PortForward],
+    ip: &str,
+    existing: &[Rule],
+    managed: &[PortForward],
+) -> Vec<Change> {
+    let mut changes = vec![];
+
+    for forward in forwards {
+        let wanted = forward.rule(ip);
+        let current = existing.iter().find(|r| forward.listens_as(r));
+
+        match current {
+            Some(rule) if *rule == wanted => (),
+            Some(rule) => {
+                changes.push(Change::Delete(rule.to_owned()));
+                changes.push(Change::Add(wanted));
+            }
+            None => changes.push(Change::Add(wanted)),
+        };
+    }
+
+    let dropped: Vec<PortForward> = managed
+        .iter()
+        .filter(|m| false == forwards.iter().any(|f| f.listens_as(&m.rule(ip))))
+        .cloned()
+        .collect();
+    changes.extend(removal(&dropped, existing));
+
+    changes
+}
+
+/// deletes for the rules in `existing` that listen where one of `forwards` does
+pub fn removal(forwards: &[PortForward], existing: &[Rule]) -> Vec<Change> {
+    existing
+        .iter()
+        .filter(|r| forwards.iter().any(|f| f.listens_as(r)))
+        .map(|r| Change::Delete(r.to_owned()))
+        .collect()
+}
+
+/// `~/.wsl2-ip-host.forwards.json`
+pub fn managed_path() -> Result<PathBuf, String> {
+    match home::home_dir() {
+        Some(p) => Ok(p.join(MANAGED_NAME)),
+        None => Err("Unable to locate home folder.".to_owned()),
+    }
+}
+
+/// the forwards recorded at `path`, none when the file doesn't exist yet
+pub fn read_managed(path: &Path) -> Result<Vec<PortForward>, String> {
+    let text = match std::fs::read(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+    };
+
+    serde_json::from_slice(&text).map_err(|e| format!("Unable to read {}: {}", path.display(), e))
+}
+
+/// records `forwards` as the ones whose rules are ours, the file goes when there are none
+pub fn save_managed(path: &Path, forwards: &[PortForward]) -> Result<(), String> {
+    let failed = |e: String| format!("Unable to write {}: {}", path.display(), e);
+
+    if forwards.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(failed(e.to_string())),
+            _ => Ok(()),
+        };
+    }
+
+    let json = serde_json::to_string_pretty(forwards).map_err(|e| failed(e.to_string()))?;
+    crate::target::replace(path, json.as_bytes()).map_err(|e| failed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "
+
+#========================
+# Port Proxy configuration
+#========================
+pushd interface portproxy
+
+reset
+add v4tov4 listenport=8080 connectaddress=172.20.112.5 connectport=80 listenaddress=0.0.0.0
+add v4tov4 listenaddress=0.0.0.0 listenport=2222 connectaddress=172.20.118.42 connectport=22
+add v4tov4 listenport=9000 connectaddress=192.168.1.20 connectport=9000 listenaddress=192.168.1.10
+add v4tov4 listenport=3000 connectaddress=172.20.112.5 connectport=3000
+add v6tov4 listenport=8080 connectaddress=172.20.112.5 connectport=80 listenaddress=::
+
+
+popd
+
+# End of Port Proxy configuration
+
+";
+
+    fn forward(text: &str) -> PortForward {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_forward_specs() {
+        assert_eq!(
+            forward("8080"),
+            PortForward {
+                listen_address: "0.0.0.0".to_owned(),
+                listen_port: 8080,
+                connect_port: 8080,
+            }
+        );
+        assert_eq!(forward("8080:80").connect_port, 80);
+        assert_eq!(forward("127.0.0.1:8080:80").listen_address, "127.0.0.1");
+        assert_eq!(
+            forward("127.0.0.1:8080:80").to_string(),
+            "127.0.0.1:8080:80"
+        );
+        assert!("http".parse::<PortForward>().is_err());
+        assert!("1:2:3:4".parse::<PortForward>().is_err());
+        assert!("lan:8080:80".parse::<PortForward>().is_err());
+        assert!("::1:8080".parse::<PortForward>().is_err());
+
+        let json = r#"{ "listen_address": "0.0.0.0 x", "listen_port": 1, "connect_port": 1 }"#;
+        let forward: PortForward = serde_json::from_str(json).unwrap();
+        assert!(forward.check().is_err());
+    }
+
+    #[test]
+    fn parses_v4tov4_rules_only() {
+        let rules = parse_dump(DUMP);
+
+        assert_eq!(rules.len(), 4);
+        assert_eq!(
+            rules[0],
+            Rule {
+                listen_address: "0.0.0.0".to_owned(),
+                listen_port: 8080,
+                connect_address: "172.20.112.5".to_owned(),
+                connect_port: 80,
+            }
+        );
+        assert_eq!(rules[2].listen_address, "192.168.1.10");
+        assert_eq!(rules[3].listen_address, "*");
+        assert!(parse_dump("").is_empty());
+    }
+
+    #[test]
+    fn plans_only_what_changed() {
+        let forwards = vec![forward("8080:80"), forward("2222:22"), forward("5432")];
+        let changes = plan(&forwards, "172.20.118.42", &parse_dump(DUMP), &[]);
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::Delete(forward("8080:80").rule("172.20.112.5")),
+                Change::Add(forward("8080:80").rule("172.20.118.42")),
+                Change::Add(forward("5432").rule("172.20.118.42")),
+            ]
+        );
+    }
+
+    #[test]
+    fn deletes_forwards_that_were_ours() {
+        let rules = parse_dump(DUMP);
+        let managed = vec![forward("8080:80"), forward("2222:22"), forward("5432")];
+
+        assert_eq!(
+            plan(&[forward("8080:80")], "172.20.112.5", &rules, &managed),
+            vec![Change::Delete(rules[1].to_owned())]
+        );
+        assert_eq!(
+            removal(&managed, &rules),
+            vec![
+                Change::Delete(rules[0].to_owned()),
+                Change::Delete(rules[1].to_owned()),
+            ]
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("wsl2-ip-host-{}.forwards.json", std::process::id()));
+        save_managed(&path, &managed).unwrap();
+        assert_eq!(read_managed(&path), Ok(managed));
+        save_managed(&path, &[]).unwrap();
+        assert!(false == path.exists());
+        assert_eq!(read_managed(&path), Ok(vec![]));
+    }
+
+    #[test]
+    fn builds_netsh_arguments() {
+        let rule = forward("8080:80").rule("172.20.118.42");
+
+        assert_eq!(
+            Change::Add(rule.to_owned()).args().join(" "),
+            "interface portproxy add v4tov4 listenaddress=0.0.0.0 listenport=8080 \
+             connectaddress=172.20.118.42 connectport=80"
+        );
+        assert_eq!(
+            Change::Delete(rule).args().join(" "),
+            "interface portproxy delete v4tov4 listenaddress=0.0.0.0 listenport=8080"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::net::Ipv4Addr;
+
+/// header of `/proc/net/route`, used to split the output of
+/// `cat /proc/net/fib_trie /proc/net/route` back into the two files
+const ROUTE_HEADER: &str = "Iface";
+
+/// a row of `/proc/net/route`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub iface: String,
+    pub destination: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub mask: Ipv4Addr,
+    pub metric: u32,
+}
+
+impl Route {
+    pub fn is_default(&self) -> bool {
+        self.destination.is_unspecified() && self.mask.is_unspecified()
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::from(self.mask);
+        u32::from(addr) & mask == u32::from(self.destination) & mask
+    }
+}
+
+/// the kernel prints addresses as the hex of the in-memory (little endian) u32
+fn parse_hex_addr(text: &str) -> Option<Ipv4Addr> {
+    u32::from_str_radix(text, 16)
+        .ok()
+        .map(|n| Ipv4Addr::from(n.to_le_bytes()))
+}
+
+pub fn parse_route(text: &str) -> Vec<Route> {
+    text.lines()
+        .filter(|line| !line.starts_with(ROUTE_HEADER))
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 8 {
+                return None;
+            }
+
+            Some(Route {
+                iface: cols[0].to_owned(),
+                destination: parse_hex_addr(cols[1])?,
+                gateway: parse_hex_addr(cols[2])?,
+                metric: cols[6].parse().ok()?,
+                mask: parse_hex_addr(cols[7])?,
+            })
+        })
+        .collect()
+}
+
+/// every `/32 host LOCAL` leaf of `/proc/net/fib_trie` except loopback, in trie order
+pub fn parse_fib_trie(text: &str) -> Vec<Ipv4Addr> {
+    let mut last = None;
+    let mut list = vec![];
+
+    for line in text.lines().map(|l| l.trim()) {
+        if let Some(addr) = line.strip_prefix("|-- ") {
+            last = addr.trim().parse::<Ipv4Addr>().ok();
+        } else if line.starts_with("/32 host LOCAL") {
+            match last {
+                Some(addr) if !addr.is_loopback() && !list.contains(&addr) => list.push(addr),
+                _ => (),
+            };
+        }
+    }
+
+    list
+}
+
+/// the default route with the lowest metric
+pub fn default_route(routes: &[Route]) -> Option<&Route> {
+    routes
+        .iter()
+        .filter(|r| r.is_default())
+        .min_by_key(|r| r.metric)
+}
+
+/// the local addresses that sit in the subnets routed through `iface`, or through the
+/// default route's interface when no interface is asked for, in trie order. an
+/// interface without routes has none, as with the `ip` strategies. the local addresses
+/// alone can't be trusted since a docker bridge usually sorts before eth0
+pub fn interface_addresses(fib_trie: &str, route: &str, iface: Option<&str>) -> Vec<Ipv4Addr> {
+    let routes = parse_route(route);
+    let locals = parse_fib_trie(fib_trie);
+
+    let iface = match (iface, default_route(&routes)) {
+        (Some(name), _) => name.to_owned(),
+        (None, Some(default)) => default.iface.to_owned(),
+        (None, None) => return vec![],
+    };
+
+    let subnets: Vec<&Route> = routes
+        .iter()
+        .filter(|r| r.iface == iface && !r.is_default())
+        .collect();
+
+    locals
+        .into_iter()
+        .filter(|addr| subnets.iter().any(|r| r.contains(*addr)))
+        .collect()
+}
+
+/// the first of `interface_addresses`
+pub fn primary_address(fib_trie: &str, route: &str, iface: Option<&str>) -> Option<Ipv4Addr> {
+    interface_addresses(fib_trie, route, iface)
+        .into_iter()
+        .next()
+}
+
+/// splits the output of `cat /proc/net/fib_trie /proc/net/route` into the two files
+pub fn split_output(text: &str) -> (&str, &str) {
+    let at = text
+        .match_indices(ROUTE_HEADER)
+        .map(|(i, _)| i)
+        .find(|i| *i == 0 || text[..*i].ends_with('\n'));
+
+    match at {
+        Some(i) => (&text[..i], &text[i..]),
+        None => (text, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UBUNTU_FIB: &str = include_str!("../tests/fixtures/ubuntu-22.04.fib_trie");
+    const UBUNTU_ROUTE: &str = include_str!("../tests/fixtures/ubuntu-22.04.route");
+    const ALPINE_FIB: &str = include_str!("../tests/fixtures/alpine-3.18.fib_trie");
+    const ALPINE_ROUTE: &str = include_str!("../tests/fixtures/alpine-3.18.route");
+
+    #[test]
+    fn parses_route_table() {
+        let routes = parse_route(UBUNTU_ROUTE);
+
+        assert_eq!(routes.len(), 3);
+        assert_eq!(
+            routes[0],
+            Route {
+                iface: "eth0".to_owned(),
+                destination: Ipv4Addr::new(0, 0, 0, 0),
+                gateway: Ipv4Addr::new(172, 20, 112, 1),
+                mask: Ipv4Addr::new(0, 0, 0, 0),
+                metric: 0,
+            }
+        );
+        assert_eq!(routes[1].destination, Ipv4Addr::new(172, 17, 0, 0));
+        assert_eq!(routes[2].mask, Ipv4Addr::new(255, 255, 240, 0));
+    }
+
+    #[test]
+    fn lists_local_addresses_without_loopback() {
+        assert_eq!(
+            parse_fib_trie(UBUNTU_FIB),
+            vec![
+                Ipv4Addr::new(172, 17, 0, 1),
+                Ipv4Addr::new(172, 20, 118, 42)
+            ]
+        );
+        assert_eq!(
+            parse_fib_trie(ALPINE_FIB),
+            vec![Ipv4Addr::new(172, 29, 64, 18)]
+        );
+    }
+
+    #[test]
+    fn picks_address_of_default_route_interface() {
+        // docker0's 172.17.0.1 sorts first in the trie but isn't on the default route
+        assert_eq!(
+            primary_address(UBUNTU_FIB, UBUNTU_ROUTE, None),
+            Some(Ipv4Addr::new(172, 20, 118, 42))
+        );
+        assert_eq!(
+            primary_address(ALPINE_FIB, ALPINE_ROUTE, None),
+            Some(Ipv4Addr::new(172, 29, 64, 18))
+        );
+    }
+
+    #[test]
+    fn takes_requested_interface_only() {
+        assert_eq!(
+            primary_address(UBUNTU_FIB, UBUNTU_ROUTE, Some("docker0")),
+            Some(Ipv4Addr::new(172, 17, 0, 1))
+        );
+        assert_eq!(
+            primary_address(UBUNTU_FIB, UBUNTU_ROUTE, Some("wlan0")),
+            None
+        );
+    }
+
+    #[test]
+    fn no_default_route_means_no_address() {
+        assert_eq!(primary_address(UBUNTU_FIB, "", None), None);
+    }
+
+    #[test]
+    fn splits_concatenated_output() {
+        let text = format!("{}{}", ALPINE_FIB, ALPINE_ROUTE);
+        let (fib, route) = split_output(&text);
+
+        assert_eq!(fib, ALPINE_FIB);
+        assert_eq!(route, ALPINE_ROUTE);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{hosts, proc_net, util, wslconfig, Discovery};
+use std::net::Ipv4Addr;
+
+/// name the windows host is written under in a distro's hosts file
+pub const DEFAULT_WINDOWS_HOST: &str = "windows.host.internal";
+const DISTRO_HOSTS_PATH: &str = "/etc/hosts";
+/// written first and moved over /etc/hosts, so a write cut short leaves the old file
+const DISTRO_HOSTS_TEMP: &str = "/etc/hosts.wsl2-ip-host";
+/// start of the name windows gives the wsl switch's adapter, `vEthernet (WSL)` or
+/// `vEthernet (WSL (Hyper-V firewall))` on newer builds
+const WSL_ADAPTER: &str = "vEthernet (WSL";
+
+/// the first ipv4 `nameserver` of a resolv.conf
+pub fn parse_nameserver(text: &str) -> Option<Ipv4Addr> {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .filter(|words| words.len() >= 2 && words[0] == "nameserver")
+        .find_map(|words| words[1].parse().ok())
+}
+
+/// the windows end of the wsl switch is the distro's default gateway. wsl also points
+/// resolv.conf at it, which is only used when there is no default route since dns
+/// tunneling swaps the nameserver for an address that isn't the host
+pub fn windows_host_address(route: &str, resolv_conf: &str) -> Option<Ipv4Addr> {
+    proc_net::default_route(&proc_net::parse_route(route))
+        .map(|r| r.gateway)
+        .filter(|gateway| !gateway.is_unspecified())
+        .or_else(|| parse_nameserver(resolv_conf))
+}
+
+/// the ipv4 address of the wsl switch's adapter in `ipconfig` output. the labels are
+/// translated on other languages of windows so the adapter's first ipv4 value is taken,
+/// which is its address ahead of the subnet mask
+pub fn parse_ipconfig(text: &str) -> Option<Ipv4Addr> {
+    let mut in_wsl = false;
+
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+
+        // adapter headers start at the first column, their details are indented
+        if false == line.starts_with(' ') {
+            in_wsl = line.contains(WSL_ADAPTER);
+            continue;
+        }
+
+        let value = match line.split_once(" : ") {
+            Some((_, value)) if in_wsl => value.trim(),
+            _ => continue,
+        };
+        // `172.20.112.1(Preferred)` on some versions
+        let address = value.split('(').next().unwrap_or(value);
+        if let Ok(ip) = address.parse() {
+            return Some(ip);
+        }
+    }
+
+    None
+}
+
+/// the windows side of the wsl switch from `ipconfig`, without asking a distro, given
+/// `discovery`'s timeout. there's no such adapter in mirrored networking mode
+pub fn find_vethernet_ip(discovery: &Discovery) -> Result<String, String> {
+    let cmd = util::hidden_cmd("ipconfig.exe");
+    let output = util::run_with_timeout(cmd, discovery.timeout, &discovery.cancel)
+        .map_err(|e| format!("Unable to run ipconfig: {}", e))?;
+
+    match parse_ipconfig(&String::from_utf8_lossy(&output.stdout)) {
+        Some(ip) => Ok(ip.to_string()),
+        None => Err(format!("ipconfig lists no {}) adapter.", WSL_ADAPTER)),
+    }
+}
+
+fn run(
+    discovery: &Discovery,
+    distro: &Option<String>,
+    user: Option<&str>,
+    args: &[&str],
+    input: Option<Vec<u8>>,
+) -> Result<String, String> {
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    let cmd = util::wsl_cmd_as(distro, user, &args);
+    let output = util::run_with_input(cmd, input, discovery.timeout, &discovery.cancel)
+        .map_err(|e| format!("{}", e))?;
+
+    if false == output.status.success() {
+        return Err(format!(
+            "Unable to run {} in the distro: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// the address the distro reaches windows on, from its default gateway or, when the
+/// distro can't tell, the wsl switch's adapter in `ipconfig`. in mirrored networking
+/// mode the distro shares windows' addresses and the gateway is the lan's router, so
+/// windows is reached on 127.0.0.1
+pub fn find_windows_host_ip(
+    discovery: &Discovery,
+    distro: &Option<String>,
+) -> Result<String, String> {
+    if let Ok(wslconfig::Mode::Mirrored) = wslconfig::read_mode() {
+        return Ok(wslconfig::LOOPBACK_IP.to_owned());
+    }
+
+    find_gateway_ip(discovery, distro).or_else(|e| {
+        log::debug!("{}, trying ipconfig", e);
+        find_vethernet_ip(discovery).map_err(|ipconfig| format!("{} {}", e, ipconfig))
+    })
+}
+
+/// the distro's default gateway, or its nameserver when there is no default route
+fn find_gateway_ip(discovery: &Discovery, distro: &Option<String>) -> Result<String, String> {
+    let route = run(discovery, distro, None, &["cat", "/proc/net/route"], None)?;
+    if let Some(ip) = windows_host_address(&route, "") {
+        return Ok(ip.to_string());
+    }
+
+    let resolv_conf = run(discovery, distro, None, &["cat", "/etc/resolv.conf"], None)?;
+    match windows_host_address(&route, &resolv_conf) {
+        Some(ip) => Ok(ip.to_string()),
+        None => Err("Unable to find a default gateway or nameserver in the distro.".to_owned()),
+    }
+}
+
+/// the distro's /etc/hosts with our lines for `names` pointing at `ip`
+pub fn preview_distro_hosts(
+    discovery: &Discovery,
+    distro: &Option<String>,
+    ip: &str,
+    names: &[String],
+) -> Result<Vec<String>, String> {
+    let text = run(discovery, distro, None, &["cat", DISTRO_HOSTS_PATH], None)?;
+    let lines: Vec<String> = text.lines().map(|l| l.to_owned()).collect();
+
+    Ok(hosts::update(ip, names, &lines))
+}
+
+/// the shell script that puts `len` bytes from stdin in place of /etc/hosts. they go to a
+/// temp file first, which only replaces the original once it holds all of them
+fn replace_script(len: usize) -> String {
+    format!(
+        "cat > {temp} && [ \"$(wc -c < {temp})\" -eq {len} ] && mv {temp} {path} || {{ rm -f {temp}; exit 1; }}",
+        temp = DISTRO_HOSTS_TEMP,
+        path = DISTRO_HOSTS_PATH,
+        len = len
+    )
+}
+
+/// rewrites the distro's /etc/hosts as root, wsl regenerates the file on boot when
+/// `generateHosts` is on so this needs repeating after a restart like the windows side
+pub fn write_distro_hosts(
+    discovery: &Discovery,
+    distro: &Option<String>,
+    ip: &str,
+    names: &[String],
+) -> Result<(), String> {
+    let lines = preview_distro_hosts(discovery, distro, ip, names)?;
+    let mut content = lines.join("\n");
+    content.push('\n');
+
+    let script = replace_script(content.len());
+    run(
+        discovery,
+        distro,
+        Some("root"),
+        &["sh", "-c", &script],
+        Some(content.into_bytes()),
+    )
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = include_str!("../tests/fixtures/ubuntu-22.04.route");
+    const IPCONFIG: &str = include_str!("../tests/fixtures/windows-11.ipconfig");
+
+    #[test]
+    fn uses_default_gateway() {
+        assert_eq!(
+            windows_host_address(ROUTE, "nameserver 10.255.255.254\n"),
+            Some(Ipv4Addr::new(172, 20, 112, 1))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_nameserver() {
+        let resolv_conf = "# This file was automatically generated by WSL.
+nameserver fe80::1
+nameserver 172.20.112.1
+";
+
+        assert_eq!(
+            windows_host_address("", resolv_conf),
+            Some(Ipv4Addr::new(172, 20, 112, 1))
+        );
+        assert_eq!(windows_host_address("", ""), None);
+    }
+
+    #[test]
+    fn replaces_hosts_only_when_complete() {
+        assert_eq!(
+            replace_script(42),
+            "cat > /etc/hosts.wsl2-ip-host && [ \"$(wc -c < /etc/hosts.wsl2-ip-host)\" -eq 42 ] && mv /etc/hosts.wsl2-ip-host /etc/hosts || { rm -f /etc/hosts.wsl2-ip-host; exit 1; }"
+        );
+    }
+
+    #[test]
+    fn finds_wsl_adapter_in_ipconfig() {
+        assert_eq!(
+            parse_ipconfig(IPCONFIG),
+            Some(Ipv4Addr::new(172, 20, 112, 1))
+        );
+
+        let german = "Ethernet-Adapter vEthernet (WSL):\r\n\r\n   IPv4-Adresse  . . . . . . . . . . : 172.28.16.1(Bevorzugt)\r\n   Subnetzmaske  . . . . . . . . . . : 255.255.240.0\r\n";
+        assert_eq!(parse_ipconfig(german), Some(Ipv4Addr::new(172, 28, 16, 1)));
+
+        let mirrored = IPCONFIG.replace("vEthernet (WSL (Hyper-V firewall))", "Ethernet 2");
+        assert_eq!(parse_ipconfig(&mirrored), None);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{
+    target, Config, Hook, LineStyle, Mapping, NameSource, OnConflict, OnMirrored, OnStopped,
+    Output, PortForward, SshConfig, Strategy,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// file name of the saved settings in the user's home folder
+pub const SAVE_NAME: &str = ".wsl2-ip-host.json";
+
+/// the saved form of a `Config`, fields added later default so older files still load
+#[derive(Serialize, Deserialize)]
+struct SaveConfig {
+    hosts_path: String,
+    domains: Vec<String>,
+    #[serde(default)]
+    subdomains: Vec<String>,
+    distro: Option<String>,
+    #[serde(default)]
+    strategies: Vec<String>,
+    #[serde(default)]
+    port_forwards: Vec<PortForward>,
+    #[serde(default)]
+    hooks: Vec<Hook>,
+    #[serde(default)]
+    ssh: Option<SshConfig>,
+    #[serde(default)]
+    outputs: Vec<Output>,
+    #[serde(default)]
+    on_conflict: OnConflict,
+    #[serde(default)]
+    line_style: LineStyle,
+    #[serde(default)]
+    static_entries: Vec<Mapping>,
+    #[serde(default)]
+    sources: Vec<NameSource>,
+    #[serde(default)]
+    mirrored: OnMirrored,
+    #[serde(default)]
+    on_stopped: OnStopped,
+}
+
+/// `~/.wsl2-ip-host.json`
+pub fn path() -> Result<PathBuf, String> {
+    match home::home_dir() {
+        Some(p) => Ok(p.join(SAVE_NAME)),
+        None => Err("Unable to locate home folder.".to_owned()),
+    }
+}
+
+pub fn from_json(json: &[u8]) -> Result<Config, String> {
+    let state: SaveConfig = serde_json::from_slice(json).map_err(|e| format!("{}", e))?;
+
+    let mut config = Config::with_hosts_path(&state.hosts_path);
+    config.names = state.domains;
+    config.subdomains = state.subdomains;
+    config.port_forwards = state.port_forwards;
+    config.hooks = state.hooks;
+    config.ssh = state.ssh;
+    config.outputs = state.outputs;
+    config.on_conflict = state.on_conflict;
+    config.line_style = state.line_style;
+    config.static_entries = state.static_entries;
+    config.sources = state.sources;
+    config.mirrored = state.mirrored;
+    config.on_stopped = state.on_stopped;
+    config.distro = state.distro;
+    let strategies: Vec<Strategy> = state
+        .strategies
+        .iter()
+        .filter_map(|s| s.parse().ok())
+        .collect();
+    if false == strategies.is_empty() {
+        config.strategies = strategies;
+    }
+
+    Ok(config)
+}
+
+pub fn to_json(config: &Config) -> Result<String, String> {
+    let save = SaveConfig {
+        hosts_path: config.hosts_path.to_owned(),
+        domains: config.names.to_owned(),
+        subdomains: config.subdomains.to_owned(),
+        distro: config.distro.to_owned(),
+        strategies: config.strategies.iter().map(|s| s.to_string()).collect(),
+        port_forwards: config.port_forwards.to_owned(),
+        hooks: config.hooks.to_owned(),
+        ssh: config.ssh.to_owned(),
+        outputs: config.outputs.to_owned(),
+        on_conflict: config.on_conflict,
+        line_style: config.line_style,
+        static_entries: config.static_entries.to_owned(),
+        sources: config.sources.to_owned(),
+        mirrored: config.mirrored,
+        on_stopped: config.on_stopped,
+    };
+
+    serde_json::to_string_pretty(&save).map_err(|e| format!("{}", e))
+}
+
+pub fn read(path: &Path) -> Result<Config, String> {
+    let content =
+        std::fs::read(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    from_json(&content).map_err(|e| format!("Unable to read {}: {}", path.display(), e))
+}
+
+pub fn save(config: &Config, path: &Path) -> Result<(), String> {
+    target::replace(path, to_json(config)?.as_bytes()).map_err(|e| format!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_files_from_older_versions() {
+        let json = br#"{
+  "hosts_path": "C:\\hosts",
+  "domains": ["host.wsl.internal"],
+  "distro": null
+}"#;
+        let config = from_json(json).unwrap();
+
+        assert_eq!(config.hosts_path, "C:\\hosts");
+        assert_eq!(config.names, vec!["host.wsl.internal".to_owned()]);
+        assert_eq!(config.strategies, Strategy::ALL.to_vec());
+        assert!(config.hooks.is_empty());
+        assert_eq!(config.ssh, None);
+    }
+
+    #[test]
+    fn round_trips_hooks() {
+        let mut config = Config::new();
+        let mut hook = Hook::new("nginx.exe");
+        hook.args = vec!["-s".to_owned(), "reload".to_owned()];
+        config.hooks.push(hook);
+
+        let loaded = from_json(to_json(&config).unwrap().as_bytes()).unwrap();
+        assert_eq!(loaded.hooks, config.hooks);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::hosts::Mapping;
+use crate::target::Update;
+use crate::{names, reverse, util, Config, Discovery};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// where a name's address comes from when it isn't the configured distro's
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Source {
+    /// `distro` for the configured distro or `distro:<name>` for another one
+    Distro(Option<String>),
+    /// `windows-host`, the address the distro reaches windows on
+    WindowsHost,
+    /// `static:<ip>`
+    Static(String),
+    /// `command:<snippet>`, the first address a shell snippet run in the distro prints,
+    /// e.g. a container's address from `docker inspect`
+    Command(String),
+}
+
+impl std::str::FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Source, String> {
+        let source = match s.split_once(':') {
+            None if s == "distro" => Source::Distro(None),
+            None if s == "windows-host" => Source::WindowsHost,
+            Some(("distro", name)) if false == name.is_empty() => {
+                Source::Distro(Some(name.to_owned()))
+            }
+            Some(("static", ip)) => match ip.parse::<std::net::IpAddr>() {
+                Ok(_) => Source::Static(ip.to_owned()),
+                Err(_) => return Err(format!("{} is not an IP address.", ip)),
+            },
+            Some(("command", snippet)) if false == snippet.trim().is_empty() => {
+                Source::Command(snippet.to_owned())
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown source {}, expected distro[:<name>], windows-host, static:<ip> or command:<snippet>",
+                    s
+                ))
+            }
+        };
+
+        Ok(source)
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Distro(None) => write!(f, "distro"),
+            Source::Distro(Some(name)) => write!(f, "distro:{}", name),
+            Source::WindowsHost => write!(f, "windows-host"),
+            Source::Static(ip) => write!(f, "static:{}", ip),
+            Source::Command(snippet) => write!(f, "command:{}", snippet),
+        }
+    }
+}
+
+impl TryFrom<String> for Source {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Source, String> {
+        s.parse()
+    }
+}
+
+impl From<Source> for String {
+    fn from(source: Source) -> String {
+        source.to_string()
+    }
+}
+
+/// the source of one configured name, a wildcard covers every name it expands to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NameSource {
+    pub name: String,
+    pub source: Source,
+}
+
+/// `name=source`, the form used on the command line
+impl std::str::FromStr for NameSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NameSource, String> {
+        match s.split_once('=') {
+            Some((name, source)) => {
+                names::check(name)?;
+                Ok(NameSource {
+                    name: name.to_owned(),
+                    source: source.parse()?,
+                })
+            }
+            None => Err(format!("Invalid source {}, expected name=source", s)),
+        }
+    }
+}
+
+/// the source declared for `name`, from the first entry that matches it
+pub fn source_of<'a>(sources: &'a [NameSource], name: &str) -> Option<&'a Source> {
+    sources
+        .iter()
+        .find(|s| names::matches(&s.name, name))
+        .map(|s| &s.source)
+}
+
+/// `(name, ip)` pairs gathered into one mapping per address, in the order each address
+/// first turns up
+pub fn group(pairs: &[(String, String)]) -> Vec<Mapping> {
+    let mut list: Vec<Mapping> = vec![];
+
+    for (name, ip) in pairs {
+        match list.iter_mut().find(|m| &m.ip == ip) {
+            Some(mapping) => mapping.names.push(name.to_owned()),
+            None => list.push(Mapping::new(ip, &[name.to_owned()])),
+        }
+    }
+
+    list
+}
+
+/// the first address in the output of a `command:` source
+pub fn parse_address(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .find(|word| word.parse::<std::net::IpAddr>().is_ok())
+        .map(|word| word.to_owned())
+}
+
+/// runs `snippet` with `sh -c` in the distro
+fn run_command(
+    discovery: &Discovery,
+    distro: &Option<String>,
+    snippet: &str,
+) -> Result<String, String> {
+    let args = vec!["sh".to_owned(), "-c".to_owned(), snippet.to_owned()];
+    let cmd = util::wsl_cmd(distro, &args);
+    let output = util::run_with_timeout(cmd, discovery.timeout, &discovery.cancel)
+        .map_err(|e| format!("{}", e))?;
+
+    if false == output.status.success() {
+        return Err(format!(
+            "command failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    parse_address(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| "command printed no address".to_owned())
+}
+
+/// the address `source` stands for, `ip` being the configured distro's
+fn lookup(
+    discovery: &Discovery,
+    config: &Config,
+    ip: &str,
+    source: &Source,
+) -> Result<String, String> {
+    match source {
+        Source::Distro(None) => Ok(ip.to_owned()),
+        Source::Distro(distro) => discovery.find_ip(distro),
+        Source::WindowsHost => reverse::find_windows_host_ip(discovery, &config.distro),
+        Source::Static(ip) => Ok(ip.to_owned()),
+        Source::Command(snippet) => run_command(discovery, &config.distro, snippet),
+    }
+}
+
+/// the update for the distro being at `ip`, with each name that declares another
+/// source looked up first. a source shared by several names is only looked up once. a
+/// name whose source can't be looked up is left out and reported in the warnings that
+/// come with the update, so one stopped container doesn't hold up every other name
+pub fn resolve(
+    discovery: &Discovery,
+    config: &Config,
+    ip: &str,
+) -> Result<(Update, Vec<String>), String> {
+    let mut update = Update::new(config, ip);
+    let mut found: Vec<(&Source, Result<String, String>)> = vec![];
+    let mut pairs = vec![];
+    let mut warnings = vec![];
+
+    for name in config.expanded_names() {
+        let source = match source_of(&config.sources, &name) {
+            Some(Source::Distro(None)) | None => {
+                pairs.push((name, ip.to_owned()));
+                continue;
+            }
+            Some(source) => source,
+        };
+
+        let address = match found.iter().find(|(s, _)| *s == source) {
+            Some((_, address)) => address.to_owned(),
+            None => {
+                let address = lookup(discovery, config, ip, source);
+                found.push((source, address.to_owned()));
+                address
+            }
+        };
+
+        match address {
+            Ok(address) => {
+                log::debug!("{} resolved {} to {}", source, name, address);
+                pairs.push((name, address));
+            }
+            Err(e) => {
+                let warning = format!("{} ({}) was left out: {}", name, source, e);
+                log::warn!("{}", warning);
+                warnings.push(warning);
+            }
+        };
+    }
+
+    // the lookups fail alike once discovery is abandoned, which is no reason to write
+    if discovery.cancel.is_cancelled() {
+        return Err("Cancelled.".to_owned());
+    }
+
+    let (ours, others): (Vec<Mapping>, Vec<Mapping>) =
+        group(&pairs).into_iter().partition(|m| m.ip == ip);
+    update.names = ours.into_iter().flat_map(|m| m.names).collect();
+    update.others = others;
+
+    Ok((update, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sources() {
+        let cases = [
+            ("distro", Source::Distro(None)),
+            ("distro:Debian", Source::Distro(Some("Debian".to_owned()))),
+            ("windows-host", Source::WindowsHost),
+            ("static:172.17.0.2", Source::Static("172.17.0.2".to_owned())),
+            (
+                "command:docker inspect -f '{{.NetworkSettings.IPAddress}}' db",
+                Source::Command("docker inspect -f '{{.NetworkSettings.IPAddress}}' db".to_owned()),
+            ),
+        ];
+
+        for (text, source) in cases.iter() {
+            assert_eq!(text.parse::<Source>().as_ref(), Ok(source));
+            assert_eq!(&source.to_string(), text);
+        }
+        assert!("static:db".parse::<Source>().is_err());
+        assert!("command: ".parse::<Source>().is_err());
+        assert!("vm".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn finds_source_by_name_or_wildcard() {
+        let sources: Vec<NameSource> = vec![
+            "db.wsl.internal=static:172.17.0.2".parse().unwrap(),
+            "*.win.internal=windows-host".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            source_of(&sources, "db.wsl.internal"),
+            Some(&Source::Static("172.17.0.2".to_owned()))
+        );
+        assert_eq!(
+            source_of(&sources, "api.win.internal"),
+            Some(&Source::WindowsHost)
+        );
+        assert_eq!(source_of(&sources, "host.wsl.internal"), None);
+
+        let json = serde_json::to_string(&sources[0]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"db.wsl.internal","source":"static:172.17.0.2"}"#
+        );
+    }
+
+    #[test]
+    fn groups_names_by_address() {
+        let pair = |name: &str, ip: &str| (name.to_owned(), ip.to_owned());
+        let mappings = group(&[
+            pair("a", "172.20.1.2"),
+            pair("db", "172.17.0.2"),
+            pair("b", "172.20.1.2"),
+        ]);
+
+        assert_eq!(
+            mappings,
+            vec![
+                Mapping::new("172.20.1.2", &["a".to_owned(), "b".to_owned()]),
+                Mapping::new("172.17.0.2", &["db".to_owned()]),
+            ]
+        );
+        assert_eq!(
+            parse_address("\n  172.17.0.2\n"),
+            Some("172.17.0.2".to_owned())
+        );
+        assert_eq!(parse_address("no such container"), None);
+    }
+}
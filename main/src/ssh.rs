@@ -0,0 +1,309 @@
+use crate::target::{join, split, Target, Update};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const BEGIN_MARKER: &str = "# BEGIN wsl2-ip-host";
+pub const END_MARKER: &str = "# END wsl2-ip-host";
+
+/// a `Host` entry kept pointing at the distro
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SshHost {
+    pub alias: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub identity_file: Option<String>,
+}
+
+impl SshHost {
+    pub fn new(alias: &str) -> SshHost {
+        SshHost {
+            alias: alias.to_owned(),
+            user: None,
+            port: None,
+            identity_file: None,
+        }
+    }
+
+    /// a value ssh would read back as written: on one line and, as there is no way to
+    /// escape one, without a `"`. the alias is a pattern, so it can't hold spaces either
+    pub fn check(&self) -> Result<(), String> {
+        if self.alias.is_empty() || self.alias.contains(char::is_whitespace) {
+            return Err(format!(
+                "Invalid ssh alias {:?}, expected a name without spaces",
+                self.alias
+            ));
+        }
+
+        let values = [
+            Some(&self.alias),
+            self.user.as_ref(),
+            self.identity_file.as_ref(),
+        ];
+        match values
+            .iter()
+            .flatten()
+            .find(|v| v.contains(['\r', '\n', '"']))
+        {
+            Some(value) => Err(format!(
+                "Invalid ssh value {:?} for {}, it can't hold a line break or a quote",
+                value, self.alias
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SshConfig {
+    pub fn check(&self) -> Result<(), String> {
+        self.hosts.iter().try_for_each(|h| h.check())
+    }
+}
+
+/// the ssh config file and the hosts we manage in it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SshConfig {
+    /// `~/.ssh/config` when not set
+    #[serde(default)]
+    pub path: Option<String>,
+    pub hosts: Vec<SshHost>,
+}
+
+impl Target for SshConfig {
+    fn describe(&self) -> String {
+        match &self.path {
+            Some(p) => format!("ssh config {}", p),
+            None => "ssh config".to_owned(),
+        }
+    }
+
+    /// `~/.ssh/config` unless another file is set, it belongs to the user so no
+    /// elevation is needed
+    fn path(&self) -> Result<PathBuf, String> {
+        match &self.path {
+            Some(p) => Ok(PathBuf::from(p)),
+            None => default_path(),
+        }
+    }
+
+    fn render(&self, current: &str, update: &Update) -> Result<String, String> {
+        self.check()?;
+        let lines = self::update(&split(current), &update.ip, &self.hosts)?;
+        Ok(join(&lines, current))
+    }
+
+    fn strip(&self, current: &str) -> Result<Option<String>, String> {
+        Ok(Some(join(&remove(&split(current))?, current)))
+    }
+}
+
+pub fn default_path() -> Result<PathBuf, String> {
+    match home::home_dir() {
+        Some(p) => Ok(p.join(".ssh").join("config")),
+        None => Err("Unable to locate home folder.".to_owned()),
+    }
+}
+
+/// `value` in double quotes when it has a space, which would otherwise end it
+fn quote(value: &str) -> String {
+    match value.contains(char::is_whitespace) {
+        true => format!("\"{}\"", value),
+        false => value.to_owned(),
+    }
+}
+
+/// our `Host` entries for `ip` between the markers, the hosts having passed `check`
+pub fn render(ip: &str, hosts: &[SshHost]) -> Vec<String> {
+    let mut lines = vec![BEGIN_MARKER.to_owned()];
+
+    for host in hosts {
+        lines.push(format!("Host {}", host.alias));
+        lines.push(format!("    HostName {}", ip));
+        if let Some(user) = &host.user {
+            lines.push(format!("    User {}", quote(user)));
+        }
+        if let Some(port) = host.port {
+            lines.push(format!("    Port {}", port));
+        }
+        if let Some(file) = &host.identity_file {
+            lines.push(format!("    IdentityFile {}", quote(file)));
+        }
+    }
+
+    lines.push(END_MARKER.to_owned());
+    lines
+}
+
+/// where our block starts and ends, an unterminated or repeated block is an error since
+/// replacing it could take the user's own entries with it
+fn find_block(lines: &[String]) -> Result<Option<(usize, usize)>, String> {
+    let mut block = None;
+    let mut begin = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        match line.trim() {
+            BEGIN_MARKER if begin.is_some() || block.is_some() => {
+                return Err(format!("Unexpected {} on line {}.", BEGIN_MARKER, i + 1))
+            }
+            BEGIN_MARKER => begin = Some(i),
+            END_MARKER => match begin.take() {
+                Some(start) => block = Some((start, i)),
+                None => return Err(format!("Unexpected {} on line {}.", END_MARKER, i + 1)),
+            },
+            _ => (),
+        };
+    }
+
+    match begin {
+        Some(start) => Err(format!(
+            "{} on line {} has no {}.",
+            BEGIN_MARKER,
+            start + 1,
+            END_MARKER
+        )),
+        None => Ok(block),
+    }
+}
+
+/// the first line that opens a `Host` or `Match` section. options above it apply to
+/// every host so a new block goes here rather than at the top, and ssh uses the first
+/// value it finds so it must come before any `Host *`
+fn first_section(lines: &[String]) -> Option<usize> {
+    lines.iter().position(|line| {
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        keyword.eq_ignore_ascii_case("host") || keyword.eq_ignore_ascii_case("match")
+    })
+}
+
+/// replaces our block with one for `ip`, or adds it ahead of the first section
+pub fn update(lines: &[String], ip: &str, hosts: &[SshHost]) -> Result<Vec<String>, String> {
+    let block = render(ip, hosts);
+    let mut list = lines.to_owned();
+
+    match find_block(lines)? {
+        Some((start, end)) => {
+            list.splice(start..=end, block);
+        }
+        None => match first_section(lines) {
+            Some(at) => {
+                let mut block = block;
+                block.push(String::new());
+                list.splice(at..at, block);
+            }
+            None => {
+                if list.last().is_some_and(|l| !l.trim().is_empty()) {
+                    list.push(String::new());
+                }
+                list.extend(block);
+            }
+        },
+    };
+
+    Ok(list)
+}
+
+/// the file without our block
+pub fn remove(lines: &[String]) -> Result<Vec<String>, String> {
+    let mut list = lines.to_owned();
+
+    if let Some((start, end)) = find_block(lines)? {
+        let blank = list.get(end + 1).is_some_and(|l| l.trim().is_empty());
+        list.drain(start..=if blank { end + 1 } else { end });
+    }
+
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "# my settings
+ServerAliveInterval 30
+
+Host github.com
+    User git
+
+Host *
+    IdentitiesOnly yes";
+
+    fn hosts() -> Vec<SshHost> {
+        let mut host = SshHost::new("ubuntu");
+        host.user = Some("me".to_owned());
+        vec![host]
+    }
+
+    #[test]
+    fn adds_block_before_first_section() {
+        let lines = update(&split(CONFIG), "172.20.1.2", &hosts()).unwrap();
+
+        assert_eq!(
+            lines.join("\n"),
+            "# my settings
+ServerAliveInterval 30
+
+# BEGIN wsl2-ip-host
+Host ubuntu
+    HostName 172.20.1.2
+    User me
+# END wsl2-ip-host
+
+Host github.com
+    User git
+
+Host *
+    IdentitiesOnly yes"
+        );
+    }
+
+    #[test]
+    fn replaces_block_in_place() {
+        let once = update(&split(CONFIG), "172.20.1.2", &hosts()).unwrap();
+        let twice = update(&once, "172.20.9.9", &hosts()).unwrap();
+
+        assert_eq!(twice.len(), once.len());
+        assert!(twice.contains(&"    HostName 172.20.9.9".to_owned()));
+        assert!(!twice.contains(&"    HostName 172.20.1.2".to_owned()));
+        assert_eq!(remove(&twice).unwrap(), split(CONFIG));
+    }
+
+    #[test]
+    fn appends_to_file_without_sections() {
+        let lines = update(&split("ServerAliveInterval 30"), "172.20.1.2", &[]).unwrap();
+
+        assert_eq!(
+            lines,
+            split("ServerAliveInterval 30\n\n# BEGIN wsl2-ip-host\n# END wsl2-ip-host")
+        );
+        assert_eq!(update(&[], "172.20.1.2", &[]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn refuses_broken_markers() {
+        let unterminated = split("Host a\n# BEGIN wsl2-ip-host\nHost b");
+        assert!(update(&unterminated, "172.20.1.2", &hosts())
+            .unwrap_err()
+            .contains("line 2"));
+
+        let stray = split("# END wsl2-ip-host");
+        assert!(remove(&stray).is_err());
+    }
+
+    #[test]
+    fn quotes_spaces_and_refuses_line_breaks() {
+        let mut host = SshHost::new("ubuntu");
+        host.identity_file = Some("C:\\Users\\Jane Doe\\.ssh\\id_ed25519".to_owned());
+        assert_eq!(host.check(), Ok(()));
+        assert_eq!(
+            render("172.20.1.2", &[host.clone()])[3],
+            "    IdentityFile \"C:\\Users\\Jane Doe\\.ssh\\id_ed25519\""
+        );
+
+        host.user = Some("me\nProxyCommand calc.exe".to_owned());
+        assert!(host.check().is_err());
+        assert!(SshHost::new("my host").check().is_err());
+        assert!(SshHost::new("").check().is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{proc_net, Family, IpQuery};
+
+/// ways of asking a distro for its address, minimal images lack one tool or another so
+/// discovery tries them in order until one produces an address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// `ip -j address show <interface>`
+    IpJson,
+    /// `ip -br address show <interface>`
+    IpBrief,
+    /// `/proc/net/fib_trie` and `/proc/net/route` worked out in rust, needs nothing
+    /// but `cat` and still tells interfaces apart
+    ProcNet,
+    /// `hostname -I`, every address of the distro regardless of interface
+    HostnameI,
+    /// every local address from `/proc/net/fib_trie` in the subnets `/proc/net/route`
+    /// sends through the interface, where `proc-net` gives only the first
+    FibTrie,
+    /// `ifconfig <interface>` from net-tools or busybox
+    Ifconfig,
+}
+
+named_enum!(Strategy, "strategy", {
+    IpJson => "ip-json",
+    IpBrief => "ip-brief",
+    ProcNet => "proc-net",
+    HostnameI => "hostname",
+    FibTrie => "fib-trie",
+    Ifconfig => "ifconfig",
+});
+
+impl Strategy {
+    /// the command run inside the distro, everything after `wsl.exe [-d distro] --`
+    pub fn args(&self, query: &IpQuery) -> Vec<String> {
+        let family = query.family.flag();
+        let iface = &query.interface[..];
+        let args = match self {
+            Strategy::IpJson => vec!["ip", "-j", family, "address", "show", iface],
+            Strategy::IpBrief => vec!["ip", family, "-br", "address", "show", iface],
+            Strategy::ProcNet => vec!["cat", "/proc/net/fib_trie", "/proc/net/route"],
+            Strategy::HostnameI => vec!["hostname", "-I"],
+            Strategy::FibTrie => vec!["cat", "/proc/net/fib_trie", "/proc/net/route"],
+            Strategy::Ifconfig => vec!["ifconfig", iface],
+        };
+
+        args.into_iter().map(|a| a.to_owned()).collect()
+    }
+
+    /// addresses found in the output of `args`, empty when there are none
+    pub fn parse(&self, text: &str, query: &IpQuery) -> Vec<String> {
+        let ips = match self {
+            Strategy::IpJson => parse_ip_json(text, query.family),
+            Strategy::IpBrief => parse_ip_brief(text),
+            Strategy::ProcNet if query.family == Family::V4 => {
+                let (fib_trie, route) = proc_net::split_output(text);
+                proc_net::primary_address(fib_trie, route, Some(&query.interface))
+                    .into_iter()
+                    .map(|addr| addr.to_string())
+                    .collect()
+            }
+            Strategy::HostnameI => parse_hostname(text, query.family),
+            Strategy::FibTrie if query.family == Family::V4 => {
+                let (fib_trie, route) = proc_net::split_output(text);
+                proc_net::interface_addresses(fib_trie, route, Some(&query.interface))
+                    .into_iter()
+                    .map(|addr| addr.to_string())
+                    .collect()
+            }
+            Strategy::ProcNet | Strategy::FibTrie => vec![],
+            Strategy::Ifconfig => parse_ifconfig(text, query.family),
+        };
+
+        dedup(ips)
+    }
+}
+
+fn dedup(list: Vec<String>) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    for ip in list {
+        if false == out.contains(&ip) {
+            out.push(ip);
+        }
+    }
+
+    out
+}
+
+fn strip_prefix_len(addr: &str) -> &str {
+    addr.split('/').next().unwrap_or(addr)
+}
+
+/// parses `ip -br address` output into a list of addresses without their subnet
+pub(crate) fn parse_ip_brief(text: &str) -> Vec<String> {
+    text.lines()
+        .flat_map(|line| line.split_whitespace().skip(2))
+        .map(strip_prefix_len)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| addr.to_owned())
+        .collect()
+}
+
+fn parse_ip_json(text: &str, family: Family) -> Vec<String> {
+    let want = match family {
+        Family::V4 => "inet",
+        Family::V6 => "inet6",
+    };
+
+    let links: Vec<serde_json::Value> = serde_json::from_str(text).unwrap_or_default();
+
+    links
+        .iter()
+        .filter_map(|link| link["addr_info"].as_array())
+        .flatten()
+        .filter(|info| info["family"].as_str() == Some(want))
+        .filter_map(|info| info["local"].as_str())
+        .map(|addr| addr.to_owned())
+        .collect()
+}
+
+fn parse_hostname(text: &str, family: Family) -> Vec<String> {
+    text.split_whitespace()
+        .filter(|addr| addr.contains(':') == (family == Family::V6))
+        .map(|addr| addr.to_owned())
+        .collect()
+}
+
+/// handles both the net-tools `inet 1.2.3.4  netmask` and busybox `inet addr:1.2.3.4`
+/// layouts
+fn parse_ifconfig(text: &str, family: Family) -> Vec<String> {
+    let want = match family {
+        Family::V4 => "inet",
+        Family::V6 => "inet6",
+    };
+
+    text.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            if words.next() != Some(want) {
+                return None;
+            }
+
+            words
+                .find(|w| *w != "addr:")
+                .map(|w| w.trim_start_matches("addr:"))
+                .map(strip_prefix_len)
+                .map(|addr| addr.to_owned())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(family: Family) -> IpQuery {
+        let mut query = IpQuery::new();
+        query.family = family;
+        query
+    }
+
+    #[test]
+    fn parses_ip_brief_output() {
+        let v4 = "eth0             UP             172.20.118.42/20 \n";
+        assert_eq!(parse_ip_brief(v4), vec!["172.20.118.42"]);
+
+        let v6 = "eth0             UP             fd00::5/64 fe80::215:5dff:fe4a:1/64 \n";
+        assert_eq!(parse_ip_brief(v6), vec!["fd00::5", "fe80::215:5dff:fe4a:1"]);
+
+        assert!(parse_ip_brief("").is_empty());
+    }
+
+    #[test]
+    fn parses_ip_json_output() {
+        let text = r#"[{"ifindex":4,"ifname":"eth0","flags":["BROADCAST","UP"],"addr_info":[
+            {"family":"inet","local":"172.20.118.42","prefixlen":20,"scope":"global"},
+            {"family":"inet6","local":"fe80::215:5dff:fe4a:1","prefixlen":64,"scope":"link"}]}]"#;
+
+        assert_eq!(
+            Strategy::IpJson.parse(text, &query(Family::V4)),
+            vec!["172.20.118.42"]
+        );
+        assert_eq!(
+            Strategy::IpJson.parse(text, &query(Family::V6)),
+            vec!["fe80::215:5dff:fe4a:1"]
+        );
+        assert!(Strategy::IpJson
+            .parse("Device \"eth0\" does not exist.", &query(Family::V4))
+            .is_empty());
+    }
+
+    #[test]
+    fn parses_hostname_output() {
+        let text = "172.20.118.42 172.17.0.1 fd00::5 \n";
+
+        assert_eq!(
+            Strategy::HostnameI.parse(text, &query(Family::V4)),
+            vec!["172.20.118.42", "172.17.0.1"]
+        );
+        assert_eq!(
+            Strategy::HostnameI.parse(text, &query(Family::V6)),
+            vec!["fd00::5"]
+        );
+    }
+
+    #[test]
+    fn parses_fib_trie_output() {
+        let text = format!(
+            "{}{}",
+            include_str!("../tests/fixtures/ubuntu-22.04.fib_trie"),
+            include_str!("../tests/fixtures/ubuntu-22.04.route")
+        );
+
+        // docker0's 172.17.0.1 sorts first in the trie but isn't routed through eth0
+        assert_eq!(
+            Strategy::FibTrie.parse(&text, &query(Family::V4)),
+            vec!["172.20.118.42"]
+        );
+        assert!(Strategy::FibTrie
+            .parse(&text, &query(Family::V6))
+            .is_empty());
+    }
+
+    #[test]
+    fn parses_proc_net_output() {
+        let text = format!(
+            "{}{}",
+            include_str!("../tests/fixtures/ubuntu-22.04.fib_trie"),
+            include_str!("../tests/fixtures/ubuntu-22.04.route")
+        );
+
+        assert_eq!(
+            Strategy::ProcNet.parse(&text, &query(Family::V4)),
+            vec!["172.20.118.42"]
+        );
+        assert!(Strategy::ProcNet
+            .parse(&text, &query(Family::V6))
+            .is_empty());
+    }
+
+    #[test]
+    fn parses_ifconfig_output() {
+        let net_tools = "eth0: flags=4163<UP,BROADCAST,RUNNING,MULTICAST>  mtu 1500
+        inet 172.20.118.42  netmask 255.255.240.0  broadcast 172.20.127.255
+        inet6 fe80::215:5dff:fe4a:1  prefixlen 64  scopeid 0x20<link>
+";
+        let busybox = "eth0      Link encap:Ethernet  HWaddr 00:15:5D:4A:00:01
+          inet addr:172.20.118.42  Bcast:172.20.127.255  Mask:255.255.240.0
+          inet6 addr: fe80::215:5dff:fe4a:1/64 Scope:Link
+";
+
+        for text in &[net_tools, busybox] {
+            assert_eq!(
+                Strategy::Ifconfig.parse(text, &query(Family::V4)),
+                vec!["172.20.118.42"]
+            );
+            assert_eq!(
+                Strategy::Ifconfig.parse(text, &query(Family::V6)),
+                vec!["fe80::215:5dff:fe4a:1"]
+            );
+        }
+    }
+
+    #[test]
+    fn strategies_round_trip_by_name() {
+        for strategy in Strategy::ALL.iter() {
+            assert_eq!(strategy.name().parse::<Strategy>(), Ok(*strategy));
+        }
+
+        assert!("route".parse::<Strategy>().is_err());
+    }
+}
@@ -0,0 +1,654 @@
+use crate::encoding::{self, Encoding};
+use crate::hosts::{self, LineStyle, Mapping, OnConflict};
+use crate::template::TemplateFile;
+use crate::Config;
+use faccess::PathExt;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_ENV_KEY: &str = "WSL_IP";
+/// plans made by `Target::write` before giving up on a file that keeps changing
+pub const WRITE_ATTEMPTS: usize = 3;
+
+/// what gets written, the same for every target
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Update {
+    pub ip: String,
+    /// with wildcards already expanded
+    pub names: Vec<String>,
+    pub distro: Option<String>,
+    /// names whose source gave them another address
+    pub others: Vec<Mapping>,
+}
+
+impl Update {
+    /// every name pointing at `ip`, see `source::resolve` for names with other sources
+    pub fn new(config: &Config, ip: &str) -> Update {
+        Update {
+            ip: ip.to_owned(),
+            names: config.expanded_names(),
+            distro: config.distro.to_owned(),
+            others: vec![],
+        }
+    }
+
+    /// the distro's names followed by the others
+    pub fn mappings(&self) -> Vec<Mapping> {
+        let mut list = vec![Mapping::new(&self.ip, &self.names)];
+        list.extend(self.others.iter().cloned());
+        list
+    }
+
+    /// every name, whatever its address
+    pub fn all_names(&self) -> Vec<String> {
+        self.mappings().into_iter().flat_map(|m| m.names).collect()
+    }
+}
+
+/// the content a target wants its file to have
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    pub path: PathBuf,
+    pub content: String,
+    /// false when the file already has this content
+    pub changed: bool,
+    /// the file's encoding, kept when it is written back
+    pub encoding: Encoding,
+    /// the file the plan was made from, `None` when it didn't exist
+    pub source: Option<Fingerprint>,
+}
+
+impl Plan {
+    pub fn lines(&self) -> Vec<String> {
+        split(&self.content)
+    }
+}
+
+/// a file's length and a hash of its bytes, to notice another program changing it
+/// between our read and our write
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fingerprint {
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    /// fnv-1a, which gives the same hash in the writer as in the process that planned
+    pub fn of(bytes: &[u8]) -> Fingerprint {
+        let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+        });
+
+        Fingerprint {
+            len: bytes.len() as u64,
+            hash,
+        }
+    }
+}
+
+/// a file as it was read
+pub struct Source {
+    pub text: String,
+    pub encoding: Encoding,
+    /// `None` when the file doesn't exist
+    pub fingerprint: Option<Fingerprint>,
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    /// the file changed after the plan was made, writing would lose that change
+    Conflict(PathBuf),
+    Failed(String),
+}
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::Conflict(path) => write!(
+                f,
+                "{} was changed by another program while it was being updated.",
+                path.display()
+            ),
+            WriteError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<WriteError> for String {
+    fn from(e: WriteError) -> String {
+        e.to_string()
+    }
+}
+
+impl From<String> for WriteError {
+    fn from(e: String) -> WriteError {
+        WriteError::Failed(e)
+    }
+}
+
+/// fails with a conflict unless `path` still holds what `expected` was taken from
+pub(crate) fn check_unchanged(
+    path: &Path,
+    expected: Option<Fingerprint>,
+) -> Result<(), WriteError> {
+    match load(path)?.fingerprint == expected {
+        true => Ok(()),
+        false => Err(WriteError::Conflict(path.to_owned())),
+    }
+}
+
+/// somewhere the ip gets written. a target only describes how its file changes, reading
+/// and writing is shared so every target treats a missing file and line endings alike
+pub trait Target {
+    /// shown when reporting what was written
+    fn describe(&self) -> String;
+
+    fn path(&self) -> Result<PathBuf, String>;
+
+    /// whether only the elevated writer can change the file
+    fn needs_elevation(&self) -> bool {
+        false
+    }
+
+    /// the file's content after writing `update` into `current`
+    fn render(&self, current: &str, update: &Update) -> Result<String, String>;
+
+    /// `current` without anything we wrote, `None` when the file should go entirely
+    fn strip(&self, current: &str) -> Result<Option<String>, String>;
+
+    fn plan(&self, update: &Update) -> Result<Plan, String> {
+        let path = self.path()?;
+        let source = load(&path)?;
+        let content = self.render(&source.text, update)?;
+
+        Ok(Plan {
+            changed: content != source.text,
+            path,
+            content,
+            encoding: source.encoding,
+            source: source.fingerprint,
+        })
+    }
+
+    /// writes `plan` unless the file no longer holds what it was planned from
+    fn apply(&self, plan: &Plan) -> Result<(), WriteError> {
+        let failed =
+            |e| WriteError::Failed(format!("Unable to write {}: {}", plan.path.display(), e));
+
+        if let Some(dir) = plan.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| failed(e.to_string()))?;
+        }
+
+        let bytes = encoding::encode(&plan.content, plan.encoding).map_err(failed)?;
+        check_unchanged(&plan.path, plan.source)?;
+        replace(&plan.path, &bytes).map_err(|e| failed(e.to_string()))
+    }
+
+    /// plans and applies `update`, planning again when another program changes the file
+    /// in between. returns whether the file was written
+    fn write(&self, update: &Update) -> Result<bool, String> {
+        let mut attempt = 1;
+
+        loop {
+            let plan = self.plan(update)?;
+            if false == plan.changed {
+                return Ok(false);
+            }
+
+            match self.apply(&plan) {
+                Ok(()) => return Ok(true),
+                Err(e @ WriteError::Conflict(_)) if attempt < WRITE_ATTEMPTS => {
+                    log::warn!("{} Planning again.", e);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            };
+        }
+    }
+
+    fn remove(&self) -> Result<(), WriteError> {
+        let path = self.path()?;
+        if false == path.exists() {
+            return Ok(());
+        }
+
+        let source = load(&path)?;
+        let content = match self.strip(&source.text)? {
+            Some(content) if content == source.text => return Ok(()),
+            Some(content) => Some(encoding::encode(&content, source.encoding)?),
+            None => None,
+        };
+
+        check_unchanged(&path, source.fingerprint)?;
+        let result = match content {
+            Some(bytes) => replace(&path, &bytes),
+            None => std::fs::remove_file(&path),
+        };
+
+        result.map_err(|e| WriteError::Failed(format!("Unable to write {}: {}", path.display(), e)))
+    }
+}
+
+/// writes `bytes` to a file next to `path` and swaps it in for `path`, so a reader
+/// never sees half a file and a failed write leaves the old one as it was. an existing
+/// file keeps its own security descriptor and attributes. when the folder can't take
+/// another file, as when only the file itself was shared for writing, it is written
+/// in place instead
+pub fn replace(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::{ErrorKind, Write};
+
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".wsl2-ip-host.tmp");
+    let temp = path.with_file_name(name);
+
+    let swapped = std::fs::File::create(&temp)
+        .and_then(|mut file| file.write_all(bytes).and_then(|_| file.sync_all()))
+        .and_then(|_| match path.exists() {
+            true => replace_file(path, &temp),
+            false => std::fs::rename(&temp, path),
+        });
+    if swapped.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+
+    match swapped {
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => std::fs::write(path, bytes),
+        result => result,
+    }
+}
+
+// winapi declares ReplaceFileW without its BOOL result
+#[link(name = "kernel32")]
+extern "system" {
+    fn ReplaceFileW(
+        replaced: *const u16,
+        replacement: *const u16,
+        backup: *const u16,
+        flags: u32,
+        exclude: *mut std::ffi::c_void,
+        reserved: *mut std::ffi::c_void,
+    ) -> i32;
+}
+
+/// `ReplaceFileW`, which unlike a rename carries the replaced file's acl over
+fn replace_file(path: &Path, replacement: &Path) -> std::io::Result<()> {
+    use std::iter::once;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+
+    let wide = |p: &Path| -> Vec<u16> { p.as_os_str().encode_wide().chain(once(0)).collect() };
+    let (replaced, replacement) = (wide(path), wide(replacement));
+
+    let done = unsafe {
+        ReplaceFileW(
+            replaced.as_ptr(),
+            replacement.as_ptr(),
+            ptr::null(),
+            0,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    match done {
+        0 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// the file at `path` with its encoding and fingerprint, empty utf-8 when it doesn't
+/// exist yet
+pub fn load(path: &Path) -> Result<Source, String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => Some(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+    };
+
+    let (text, encoding) = encoding::decode(bytes.as_deref().unwrap_or_default())
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+
+    Ok(Source {
+        text,
+        encoding,
+        fingerprint: bytes.as_deref().map(Fingerprint::of),
+    })
+}
+
+/// the file at `path` and its encoding, empty utf-8 when it doesn't exist yet
+pub fn read_encoded(path: &Path) -> Result<(String, Encoding), String> {
+    load(path).map(|source| (source.text, source.encoding))
+}
+
+/// the file at `path`, empty when it doesn't exist yet
+pub fn read(path: &Path) -> Result<String, String> {
+    read_encoded(path).map(|(text, _)| text)
+}
+
+pub fn split(text: &str) -> Vec<String> {
+    text.lines().map(|l| l.to_owned()).collect()
+}
+
+/// `lines` with the line ending `original` uses, `\n` for a new file, ending in one
+/// unless `original` had text after its last line ending
+pub fn join(lines: &[String], original: &str) -> String {
+    let newline = if original.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let trailing = original.is_empty() || original.ends_with('\n');
+
+    match (lines.is_empty(), trailing) {
+        (true, _) => String::new(),
+        (false, true) => format!("{}{}", lines.join(newline), newline),
+        (false, false) => lines.join(newline),
+    }
+}
+
+/// the windows hosts file, which takes the elevated writer unless it is somewhere else
+/// that we can write to
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostsFile {
+    pub path: String,
+    pub on_conflict: OnConflict,
+    pub line_style: LineStyle,
+    /// fixed lines written after the distro's
+    pub static_entries: Vec<Mapping>,
+}
+
+impl HostsFile {
+    pub fn new(path: &str) -> HostsFile {
+        HostsFile {
+            path: path.to_owned(),
+            on_conflict: OnConflict::default(),
+            line_style: LineStyle::default(),
+            static_entries: vec![],
+        }
+    }
+
+    /// lines we don't manage that map one of the names
+    pub fn conflicts(&self, update: &Update) -> Result<Vec<hosts::Conflict>, String> {
+        let current = read(&self.path()?)?;
+        Ok(hosts::conflicts(&update.all_names(), &split(&current)))
+    }
+}
+
+impl Target for HostsFile {
+    fn describe(&self) -> String {
+        format!("hosts file {}", self.path)
+    }
+
+    fn path(&self) -> Result<PathBuf, String> {
+        Ok(PathBuf::from(&self.path))
+    }
+
+    fn needs_elevation(&self) -> bool {
+        false == Path::new(&self.path).writable()
+    }
+
+    fn render(&self, current: &str, update: &Update) -> Result<String, String> {
+        let lines = hosts::update_with_policy(
+            &update.mappings(),
+            &split(current),
+            self.on_conflict,
+            self.line_style,
+        );
+        let statics = hosts::static_entries(&self.static_entries, self.line_style);
+        Ok(join(&hosts::insert(&lines, statics), current))
+    }
+
+    /// takes our lines out and puts back any we commented out
+    fn strip(&self, current: &str) -> Result<Option<String>, String> {
+        let lines = hosts::restore(&hosts::clean(&split(current)));
+        Ok(Some(join(&lines, current)))
+    }
+}
+
+/// a `KEY=ip` line in a dotenv style file, e.g. for docker compose
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnvFile {
+    pub path: String,
+    #[serde(default = "default_env_key")]
+    pub key: String,
+}
+
+fn default_env_key() -> String {
+    DEFAULT_ENV_KEY.to_owned()
+}
+
+impl EnvFile {
+    fn is_ours(&self, line: &str) -> bool {
+        let line = line.trim_start();
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        line.split('=').next().map(|k| k.trim()) == Some(&self.key[..]) && line.contains('=')
+    }
+}
+
+impl Target for EnvFile {
+    fn describe(&self) -> String {
+        format!("{} in {}", self.key, self.path)
+    }
+
+    fn path(&self) -> Result<PathBuf, String> {
+        Ok(PathBuf::from(&self.path))
+    }
+
+    /// replaces the first assignment to `key` and drops any later ones, or appends one
+    fn render(&self, current: &str, update: &Update) -> Result<String, String> {
+        let line = format!("{}={}", self.key, update.ip);
+        let mut lines = vec![];
+        let mut found = false;
+
+        for l in split(current) {
+            if false == self.is_ours(&l) {
+                lines.push(l);
+            } else if false == found {
+                lines.push(line.to_owned());
+                found = true;
+            }
+        }
+
+        if false == found {
+            lines.push(line);
+        }
+
+        Ok(join(&lines, current))
+    }
+
+    fn strip(&self, current: &str) -> Result<Option<String>, String> {
+        let lines: Vec<String> = split(current)
+            .into_iter()
+            .filter(|l| !self.is_ours(l))
+            .collect();
+
+        Ok(Some(join(&lines, current)))
+    }
+}
+
+/// a json file describing the last write for other tools to read
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatusFile {
+    pub path: String,
+}
+
+impl Target for StatusFile {
+    fn describe(&self) -> String {
+        format!("status file {}", self.path)
+    }
+
+    fn path(&self) -> Result<PathBuf, String> {
+        Ok(PathBuf::from(&self.path))
+    }
+
+    fn render(&self, _current: &str, update: &Update) -> Result<String, String> {
+        let mut json = serde_json::to_string_pretty(update).map_err(|e| format!("{}", e))?;
+        json.push('\n');
+        Ok(json)
+    }
+
+    fn strip(&self, _current: &str) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+}
+
+/// extra files kept up to date alongside the hosts file, as saved in the settings
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Output {
+    EnvFile(EnvFile),
+    StatusFile(StatusFile),
+    Template(TemplateFile),
+}
+
+impl Output {
+    pub fn target(&self) -> Box<dyn Target> {
+        match self {
+            Output::EnvFile(t) => Box::new(t.clone()),
+            Output::StatusFile(t) => Box::new(t.clone()),
+            Output::Template(t) => Box::new(t.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(ip: &str) -> Update {
+        Update {
+            ip: ip.to_owned(),
+            names: vec!["host.wsl.internal".to_owned()],
+            distro: Some("Ubuntu".to_owned()),
+            others: vec![],
+        }
+    }
+
+    #[test]
+    fn hosts_file_keeps_crlf() {
+        let target = HostsFile::new("hosts");
+        let current = "127.0.0.1 localhost\r\n";
+        let content = target.render(current, &update("172.20.1.2")).unwrap();
+
+        assert_eq!(
+            content,
+            "127.0.0.1 localhost\r\n172.20.1.2 host.wsl.internal # added by wsl2-ip-host\r\n"
+        );
+        assert_eq!(target.strip(&content).unwrap(), Some(current.to_owned()));
+    }
+
+    #[test]
+    fn hosts_file_keeps_missing_trailing_newline() {
+        let target = HostsFile::new("hosts");
+
+        assert_eq!(
+            target
+                .render("127.0.0.1 localhost", &update("172.20.1.2"))
+                .unwrap(),
+            "127.0.0.1 localhost\n172.20.1.2 host.wsl.internal # added by wsl2-ip-host"
+        );
+    }
+
+    #[test]
+    fn writes_back_in_the_encoding_read() {
+        let dir = std::env::temp_dir().join(format!("wsl2-ip-host-enc-{}", std::process::id()));
+        let target = HostsFile::new(&dir.join("hosts").to_string_lossy());
+        let original =
+            encoding::encode("# caf\u{e9}\r\n", Encoding::Utf16Le { bom: true }).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&target.path, &original).unwrap();
+
+        let plan = target.plan(&update("172.20.1.2")).unwrap();
+        assert_eq!(plan.encoding, Encoding::Utf16Le { bom: true });
+        target.apply(&plan).unwrap();
+        target.remove().unwrap();
+
+        assert_eq!(std::fs::read(&target.path).unwrap(), original);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn apply_refuses_a_file_changed_since_the_plan() {
+        let dir = std::env::temp_dir().join(format!("wsl2-ip-host-race-{}", std::process::id()));
+        let target = HostsFile::new(&dir.join("hosts").to_string_lossy());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&target.path, "127.0.0.1 localhost\n").unwrap();
+
+        let plan = target.plan(&update("172.20.1.2")).unwrap();
+        std::fs::write(&target.path, "127.0.0.1 localhost\n10.0.0.5 vpn.corp\n").unwrap();
+
+        assert!(matches!(target.apply(&plan), Err(WriteError::Conflict(_))));
+        assert!(target.write(&update("172.20.1.2")).unwrap());
+        assert_eq!(
+            read(&plan.path).unwrap(),
+            "127.0.0.1 localhost\n10.0.0.5 vpn.corp\n172.20.1.2 host.wsl.internal # added by wsl2-ip-host\n"
+        );
+        assert!(!target.write(&update("172.20.1.2")).unwrap());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn env_file_replaces_only_its_key() {
+        let target = EnvFile {
+            path: ".env".to_owned(),
+            key: DEFAULT_ENV_KEY.to_owned(),
+        };
+        let current =
+            "COMPOSE_PROJECT_NAME=app\nWSL_IP=172.20.1.2\nWSL_IP_OLD=1\nexport WSL_IP=x\n";
+
+        assert_eq!(
+            target.render(current, &update("172.20.9.9")).unwrap(),
+            "COMPOSE_PROJECT_NAME=app\nWSL_IP=172.20.9.9\nWSL_IP_OLD=1\n"
+        );
+        assert_eq!(
+            target.render("", &update("172.20.9.9")).unwrap(),
+            "WSL_IP=172.20.9.9\n"
+        );
+        assert_eq!(
+            target.strip(current).unwrap(),
+            Some("COMPOSE_PROJECT_NAME=app\nWSL_IP_OLD=1\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn status_file_is_json() {
+        let target = StatusFile {
+            path: "status.json".to_owned(),
+        };
+        let content = target.render("", &update("172.20.1.2")).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&content).unwrap();
+
+        assert_eq!(value["ip"], "172.20.1.2");
+        assert_eq!(value["distro"], "Ubuntu");
+        assert_eq!(target.strip(&content).unwrap(), None);
+    }
+
+    #[test]
+    fn outputs_are_tagged_by_type() {
+        let outputs: Vec<Output> = serde_json::from_str(
+            r#"[{ "type": "env-file", "path": ".env" }, { "type": "status-file", "path": "s.json" }]"#,
+        )
+        .unwrap();
+
+        assert_eq!(outputs[0].target().describe(), "WSL_IP in .env");
+        assert_eq!(outputs[1].target().describe(), "status file s.json");
+    }
+
+    #[test]
+    fn plan_notices_unchanged_file() {
+        let dir = std::env::temp_dir().join(format!("wsl2-ip-host-{}", std::process::id()));
+        let target = EnvFile {
+            path: dir.join(".env").to_string_lossy().into_owned(),
+            key: DEFAULT_ENV_KEY.to_owned(),
+        };
+
+        let plan = target.plan(&update("172.20.1.2")).unwrap();
+        assert!(plan.changed);
+        target.apply(&plan).unwrap();
+        assert!(!target.plan(&update("172.20.1.2")).unwrap().changed);
+
+        target.remove().unwrap();
+        assert_eq!(read(&plan.path).unwrap(), "");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use crate::target::{read, Target, Update};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// extension stripped from a template's path to get the file it renders to
+pub const TEMPLATE_EXTENSION: &str = ".tmpl";
+
+/// the value of each placeholder, `names` are space separated
+pub fn variables(update: &Update) -> Vec<(&'static str, String)> {
+    vec![
+        ("ip", update.ip.to_owned()),
+        ("distro", update.distro.to_owned().unwrap_or_default()),
+        ("names", update.names.join(" ")),
+    ]
+}
+
+/// replaces each `{{name}}` in `text`, spaces inside the braces are allowed. a
+/// placeholder that isn't in `vars` or never closes is an error naming its line
+pub fn render(text: &str, vars: &[(&str, String)]) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+
+        let mut rest = line;
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let column = line.len() - rest.len() + start + 1;
+            let after = &rest[start + 2..];
+
+            let end = after.find("}}").ok_or_else(|| {
+                format!("line {}, column {}: {{{{ is never closed", i + 1, column)
+            })?;
+            let name = after[..end].trim();
+
+            match vars.iter().find(|(k, _)| *k == name) {
+                Some((_, value)) => out.push_str(value),
+                None => {
+                    return Err(format!(
+                        "line {}, column {}: unknown placeholder {{{{{}}}}}",
+                        i + 1,
+                        column,
+                        name
+                    ))
+                }
+            };
+
+            rest = &after[end + 2..];
+        }
+
+        out.push_str(rest);
+    }
+
+    Ok(out)
+}
+
+/// whether `a` and `b` name the same file, without case as windows compares them
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a
+            .to_string_lossy()
+            .replace('/', "\\")
+            .eq_ignore_ascii_case(&b.to_string_lossy().replace('/', "\\")),
+    }
+}
+
+/// a file rendered from a template each time the ip changes, e.g. an nginx upstream or
+/// a `.env` for docker compose
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TemplateFile {
+    pub template: String,
+    /// the template's path without `.tmpl` when not set
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl Target for TemplateFile {
+    fn describe(&self) -> String {
+        format!("template {}", self.template)
+    }
+
+    fn path(&self) -> Result<PathBuf, String> {
+        match (&self.path, self.template.strip_suffix(TEMPLATE_EXTENSION)) {
+            (Some(p), _) if same_file(Path::new(p), Path::new(&self.template)) => Err(format!(
+                "Template {} can't be rendered over itself, its output needs another path.",
+                self.template
+            )),
+            (Some(p), _) => Ok(PathBuf::from(p)),
+            (None, Some(p)) if !p.is_empty() => Ok(PathBuf::from(p)),
+            _ => Err(format!(
+                "Template {} needs an output path or a {} extension.",
+                self.template, TEMPLATE_EXTENSION
+            )),
+        }
+    }
+
+    fn render(&self, _current: &str, update: &Update) -> Result<String, String> {
+        let path = PathBuf::from(&self.template);
+        if false == path.exists() {
+            return Err(format!("Unable to find template {}", self.template));
+        }
+
+        render(&read(&path)?, &variables(update)).map_err(|e| format!("{}: {}", self.template, e))
+    }
+
+    /// the output is deleted when it is named after the template, so it was ours to
+    /// begin with. one at a `path` of its own may be a file that was there before, such
+    /// as a compose `.env`, and is left as it is
+    fn strip(&self, current: &str) -> Result<Option<String>, String> {
+        match self.path {
+            Some(_) => Ok(Some(current.to_owned())),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vec<(&'static str, String)> {
+        variables(&Update {
+            ip: "172.20.1.2".to_owned(),
+            names: vec!["a.wsl".to_owned(), "b.wsl".to_owned()],
+            distro: Some("Ubuntu".to_owned()),
+            others: vec![],
+        })
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        let template = "upstream app {\n    server {{ip}}:8080;\n}\n# {{ distro }}: {{names}}\n";
+
+        assert_eq!(
+            render(template, &vars()).unwrap(),
+            "upstream app {\n    server 172.20.1.2:8080;\n}\n# Ubuntu: a.wsl b.wsl\n"
+        );
+        assert_eq!(
+            render("no placeholders\r\n", &vars()).unwrap(),
+            "no placeholders\r\n"
+        );
+    }
+
+    #[test]
+    fn reports_line_of_unknown_placeholder() {
+        let err = render("WSL_IP={{ip}}\n\nPORT={{port}}", &vars()).unwrap_err();
+        assert_eq!(err, "line 3, column 6: unknown placeholder {{port}}");
+    }
+
+    #[test]
+    fn reports_unclosed_placeholder() {
+        let err = render("a\nserver {{ip:80;", &vars()).unwrap_err();
+        assert_eq!(err, "line 2, column 8: {{ is never closed");
+    }
+
+    #[test]
+    fn output_defaults_to_template_without_extension() {
+        let template = |t: &str| TemplateFile {
+            template: t.to_owned(),
+            path: None,
+        };
+
+        assert_eq!(
+            template("nginx.conf.tmpl").path().unwrap(),
+            PathBuf::from("nginx.conf")
+        );
+        assert!(template("nginx.conf").path().is_err());
+    }
+
+    #[test]
+    fn output_is_never_the_template() {
+        let template = |path: &str| TemplateFile {
+            template: "conf/.env.tmpl".to_owned(),
+            path: Some(path.to_owned()),
+        };
+
+        assert!(template("conf/.env.tmpl").path().is_err());
+        assert!(template("Conf\\.ENV.tmpl").path().is_err());
+        assert_eq!(template("conf/.env").path(), Ok(PathBuf::from("conf/.env")));
+
+        assert_eq!(
+            template("conf/.env").strip("A=1\n"),
+            Ok(Some("A=1\n".to_owned()))
+        );
+        assert_eq!(
+            TemplateFile {
+                path: None,
+                ..template("")
+            }
+            .strip("A=1\n"),
+            Ok(None)
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use crate::encoding::{self, Encoding};
+use crate::target::{self, Fingerprint};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// file name of wsl's global settings in the user's home folder
+pub const WSLCONFIG_NAME: &str = ".wslconfig";
+/// where names point when the distro shares windows' addresses
+pub const LOOPBACK_IP: &str = "127.0.0.1";
+
+/// a section of an ini file and its `key = value` lines in file order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Section {
+    /// empty for lines ahead of the first `[section]`
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+/// the sections of an ini file like `.wslconfig`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ini {
+    pub sections: Vec<Section>,
+}
+
+impl Ini {
+    /// the last value of `key` in `section`, both compared without case as wsl does
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections
+            .iter()
+            .filter(|s| s.name.eq_ignore_ascii_case(section))
+            .flat_map(|s| s.entries.iter())
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| &v[..])
+            .next_back()
+    }
+}
+
+/// where a ` # comment` after a value starts
+fn comment_at(text: &str) -> Option<usize> {
+    [" #", "\t#", " ;", "\t;"]
+        .iter()
+        .filter_map(|marker| text.find(marker))
+        .min()
+}
+
+/// `value` without a trailing ` # comment` and surrounding quotes
+fn value(text: &str) -> String {
+    let end = comment_at(text).unwrap_or(text.len());
+    let text = text[..end].trim();
+
+    match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(quoted) => quoted.to_owned(),
+        None => text.to_owned(),
+    }
+}
+
+/// `value` as it has to be written for `value()` to read it back: quoted when it has
+/// spaces at either end or quotes of its own around it. one with a comment marker in it
+/// can't be written at all
+fn quote(value: &str) -> Result<String, String> {
+    if comment_at(value).is_some() {
+        return Err(format!(
+            "{} would be cut at the comment marker, leave out the space before # or ;.",
+            value
+        ));
+    }
+
+    let quoted = value.len() > 1 && value.starts_with('"') && value.ends_with('"');
+    match quoted || value.trim() != value {
+        true => Ok(format!("\"{}\"", value)),
+        false => Ok(value.to_owned()),
+    }
+}
+
+/// what a line of an ini file holds
+#[derive(Debug, PartialEq)]
+enum Line<'a> {
+    Section(&'a str),
+    /// key and the text after `=`
+    Entry(&'a str, &'a str),
+    /// blank lines, comments and anything wsl would ignore
+    Other,
+}
+
+fn classify(line: &str) -> Line<'_> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+        return Line::Other;
+    }
+
+    if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+        return Line::Section(name.trim());
+    }
+
+    match line.split_once('=') {
+        Some((key, rest)) => Line::Entry(key.trim(), rest),
+        None => Line::Other,
+    }
+}
+
+/// reads `[section]` headers and `key = value` lines. `#` and `;` start comments and a
+/// line that is neither is passed over, as wsl only warns about it
+pub fn parse(text: &str) -> Ini {
+    let mut ini = Ini::default();
+    let mut section = Section::default();
+
+    for line in text.lines() {
+        match classify(line) {
+            Line::Section(name) => {
+                let next = Section {
+                    name: name.to_owned(),
+                    entries: vec![],
+                };
+                ini.sections.push(std::mem::replace(&mut section, next));
+            }
+            Line::Entry(key, rest) => section.entries.push((key.to_owned(), value(rest))),
+            Line::Other => (),
+        };
+    }
+
+    ini.sections.push(section);
+    ini.sections
+        .retain(|s| false == s.name.is_empty() || false == s.entries.is_empty());
+    ini
+}
+
+/// `section.key` as the cli takes it, the section being `wsl2` when left out
+pub fn split_key(text: &str) -> Result<(String, String), String> {
+    let (section, key) = match text.split_once('.') {
+        Some((section, key)) => (section, key),
+        None => ("wsl2", text),
+    };
+
+    let valid = |s: &str| {
+        false == s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+    };
+    match valid(section) && valid(key) {
+        true => Ok((section.to_owned(), key.to_owned())),
+        false => Err(format!("Invalid setting {}, expected section.key", text)),
+    }
+}
+
+/// `.wslconfig` kept line by line, so editing a setting leaves the comments, blank lines
+/// and the order of everything else as they were
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WslConfig {
+    lines: Vec<String>,
+    /// the text it was read from, for its line endings and final newline
+    original: String,
+}
+
+impl WslConfig {
+    pub fn parse(text: &str) -> WslConfig {
+        WslConfig {
+            lines: target::split(text),
+            original: text.to_owned(),
+        }
+    }
+
+    pub fn ini(&self) -> Ini {
+        parse(&self.lines.join("\n"))
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<String> {
+        self.ini().get(section, key).map(|v| v.to_owned())
+    }
+
+    /// every setting as (section, key, value) in file order
+    pub fn entries(&self) -> Vec<(String, String, String)> {
+        self.ini()
+            .sections
+            .into_iter()
+            .flat_map(|s| {
+                let name = s.name;
+                s.entries
+                    .into_iter()
+                    .map(move |(k, v)| (name.to_owned(), k, v))
+            })
+            .collect()
+    }
+
+    /// the section each line is in, by index
+    fn sections(&self) -> Vec<String> {
+        let mut current = String::new();
+        self.lines
+            .iter()
+            .map(|line| {
+                if let Line::Section(name) = classify(line) {
+                    current = name.to_owned();
+                }
+                current.to_owned()
+            })
+            .collect()
+    }
+
+    /// the indexes of `key`'s lines in `section`
+    fn find(&self, section: &str, key: &str) -> Vec<usize> {
+        let sections = self.sections();
+        (0..self.lines.len())
+            .filter(|i| sections[*i].eq_ignore_ascii_case(section))
+            .filter(|i| match classify(&self.lines[*i]) {
+                Line::Entry(k, _) => k.eq_ignore_ascii_case(key),
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// changes the value of `key` in place, keeping a comment after it, or adds it at
+    /// the end of the last `[section]`, which is added when there's none
+    pub fn set(&mut self, section: &str, key: &str, value: &str) -> Result<(), String> {
+        if value.contains(['\r', '\n']) {
+            return Err(format!(
+                "The value for {}.{} can't span lines.",
+                section, key
+            ));
+        }
+        let value = &quote(value).map_err(|e| format!("{}.{}: {}", section, key, e))?;
+
+        if let Some(at) = self.find(section, key).last().copied() {
+            let (head, rest) = self.lines[at].split_once('=').unwrap_or_default();
+            let pad = if rest.starts_with(' ') { " " } else { "" };
+            // the comment keeps the spacing it had after the old value
+            let comment = comment_at(rest)
+                .map(|c| &rest[rest[..c].trim_end().len()..])
+                .unwrap_or_default();
+            self.lines[at] = format!("{}={}{}{}", head, pad, value, comment);
+            return Ok(());
+        }
+
+        let line = format!("{}={}", key, value);
+        let sections = self.sections();
+        let last = (0..self.lines.len())
+            .filter(|i| sections[*i].eq_ignore_ascii_case(section))
+            .rfind(|i| classify(&self.lines[*i]) != Line::Other);
+
+        match last {
+            Some(at) => self.lines.insert(at + 1, line),
+            None => {
+                if self
+                    .lines
+                    .last()
+                    .is_some_and(|l| false == l.trim().is_empty())
+                {
+                    self.lines.push(String::new());
+                }
+                self.lines.push(format!("[{}]", section));
+                self.lines.push(line);
+            }
+        };
+
+        Ok(())
+    }
+
+    /// takes `key` out of `section`, returns whether it was there
+    pub fn unset(&mut self, section: &str, key: &str) -> bool {
+        let found = self.find(section, key);
+        for at in found.iter().rev() {
+            self.lines.remove(*at);
+        }
+        false == found.is_empty()
+    }
+
+    /// the file's text with the original line endings and final newline
+    pub fn text(&self) -> String {
+        target::join(&self.lines, &self.original)
+    }
+}
+
+/// `.wslconfig` as read, kept with what's needed to write it back the same way
+pub struct Loaded {
+    pub config: WslConfig,
+    pub path: PathBuf,
+    encoding: Encoding,
+    fingerprint: Option<Fingerprint>,
+}
+
+impl Loaded {
+    pub fn read(path: &Path) -> Result<Loaded, String> {
+        let source = target::load(path)?;
+
+        Ok(Loaded {
+            config: WslConfig::parse(&source.text),
+            path: path.to_owned(),
+            encoding: source.encoding,
+            fingerprint: source.fingerprint,
+        })
+    }
+
+    /// writes the edited config back in its encoding, unless another program changed the
+    /// file since it was read. returns whether there was anything to write
+    pub fn save(&self) -> Result<bool, String> {
+        let text = self.config.text();
+        if text == self.config.original {
+            return Ok(false);
+        }
+
+        let failed = |e: String| format!("Unable to write {}: {}", self.path.display(), e);
+        let bytes = encoding::encode(&text, self.encoding).map_err(failed)?;
+        target::check_unchanged(&self.path, self.fingerprint)?;
+        target::replace(&self.path, &bytes).map_err(|e| failed(e.to_string()))?;
+
+        Ok(true)
+    }
+}
+
+/// how wsl 2 connects distros to the network, `networkingMode` in `.wslconfig`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Mode {
+    /// the distro sits behind the `vEthernet (WSL)` switch with an address of its own
+    #[default]
+    Nat,
+    /// the distro shares windows' addresses, so it is reached on localhost
+    Mirrored,
+    /// the distro is on the lan through a bridged adapter, eth0 holds that address
+    Bridged,
+    /// `virtioproxy`, `none` or a mode added later, treated like nat
+    Other(String),
+}
+
+impl Mode {
+    pub fn from_ini(ini: &Ini) -> Mode {
+        // mirrored mode first shipped under [experimental]
+        let value = ini
+            .get("wsl2", "networkingMode")
+            .or_else(|| ini.get("experimental", "networkingMode"));
+
+        match value.map(|v| v.to_ascii_lowercase()) {
+            None => Mode::Nat,
+            Some(v) if v == "nat" || v.is_empty() => Mode::Nat,
+            Some(v) if v == "mirrored" => Mode::Mirrored,
+            Some(v) if v == "bridged" => Mode::Bridged,
+            Some(v) => Mode::Other(v),
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Nat => write!(f, "nat"),
+            Mode::Mirrored => write!(f, "mirrored"),
+            Mode::Bridged => write!(f, "bridged"),
+            Mode::Other(mode) => write!(f, "{}", mode),
+        }
+    }
+}
+
+/// what to write when the distro shares windows' addresses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnMirrored {
+    /// point the names at 127.0.0.1
+    #[default]
+    Loopback,
+    /// leave every file as it is
+    Skip,
+}
+
+named_enum!(OnMirrored, "mirrored behaviour", {
+    Loopback => "loopback",
+    Skip => "skip",
+});
+
+/// `%USERPROFILE%\.wslconfig`
+pub fn path() -> Result<PathBuf, String> {
+    match home::home_dir() {
+        Some(p) => Ok(p.join(WSLCONFIG_NAME)),
+        None => Err("Unable to locate home folder.".to_owned()),
+    }
+}
+
+/// the networking mode in `.wslconfig`, nat when there's no such file
+pub fn read_mode() -> Result<Mode, String> {
+    let path = path()?;
+    if false == path.exists() {
+        return Ok(Mode::Nat);
+    }
+
+    let text = target::read(&path)?;
+    Ok(Mode::from_ini(&parse(&text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WSLCONFIG: &str = "# Settings apply across all Linux distros running on WSL 2\r
+[wsl2]\r
+memory=4GB \r
+; networkingMode=bridged\r
+NetworkingMode = \"Mirrored\"  # localhost works both ways\r
+vmSwitch=\r
+\r
+[experimental]\r
+autoMemoryReclaim=gradual\r
+not a setting\r
+";
+
+    #[test]
+    fn parses_sections_and_comments() {
+        let ini = parse(WSLCONFIG);
+
+        assert_eq!(ini.sections.len(), 2);
+        assert_eq!(ini.get("WSL2", "memory"), Some("4GB"));
+        assert_eq!(ini.get("wsl2", "networkingmode"), Some("Mirrored"));
+        assert_eq!(ini.get("wsl2", "vmSwitch"), Some(""));
+        assert_eq!(
+            ini.get("experimental", "autoMemoryReclaim"),
+            Some("gradual")
+        );
+        assert_eq!(ini.get("experimental", "memory"), None);
+        assert_eq!(ini.sections[1].entries.len(), 1);
+    }
+
+    #[test]
+    fn later_values_win() {
+        let ini = parse("[wsl2]\nnetworkingMode=mirrored\n[wsl2]\nnetworkingMode=NAT\n");
+        assert_eq!(ini.get("wsl2", "networkingMode"), Some("NAT"));
+        assert_eq!(Mode::from_ini(&ini), Mode::Nat);
+    }
+
+    #[test]
+    fn edits_keep_comments_and_order() {
+        let mut config = WslConfig::parse(WSLCONFIG);
+
+        config.set("wsl2", "networkingMode", "nat").unwrap();
+        config.set("WSL2", "dnsTunneling", "true").unwrap();
+        config.set("experimental", "sparseVhd", "true").unwrap();
+        config.set("boot", "systemd", "true").unwrap();
+        assert!(config.unset("wsl2", "memory"));
+        assert!(false == config.unset("wsl2", "memory"));
+
+        assert_eq!(
+            config.text(),
+            "# Settings apply across all Linux distros running on WSL 2\r
+[wsl2]\r
+; networkingMode=bridged\r
+NetworkingMode = nat  # localhost works both ways\r
+vmSwitch=\r
+dnsTunneling=true\r
+\r
+[experimental]\r
+autoMemoryReclaim=gradual\r
+sparseVhd=true\r
+not a setting\r
+\r
+[boot]\r
+systemd=true\r
+"
+        );
+        assert_eq!(config.get("wsl2", "networkingMode"), Some("nat".to_owned()));
+        assert!(config.set("wsl2", "kernel", "a\nb").is_err());
+
+        let mut empty = WslConfig::parse("");
+        empty.set("wsl2", "memory", "8GB").unwrap();
+        assert_eq!(empty.text(), "[wsl2]\nmemory=8GB\n");
+
+        for value in [" padded ", "\"quoted\"", "a#b"].iter() {
+            empty.set("wsl2", "kernelCommandLine", value).unwrap();
+            assert_eq!(
+                empty.get("wsl2", "kernelCommandLine").as_deref(),
+                Some(*value)
+            );
+        }
+        assert!(empty.set("wsl2", "kernelCommandLine", "a # b").is_err());
+        assert!(empty.set("wsl2", "kernelCommandLine", "a ;b").is_err());
+    }
+
+    #[test]
+    fn splits_setting_names() {
+        assert_eq!(
+            split_key("experimental.sparseVhd"),
+            Ok(("experimental".to_owned(), "sparseVhd".to_owned()))
+        );
+        assert_eq!(
+            split_key("memory"),
+            Ok(("wsl2".to_owned(), "memory".to_owned()))
+        );
+        assert!(split_key("wsl2.").is_err());
+        assert!(split_key("wsl2.a=b").is_err());
+    }
+
+    #[test]
+    fn detects_networking_mode() {
+        let mode = |text: &str| Mode::from_ini(&parse(text));
+
+        assert_eq!(mode(WSLCONFIG), Mode::Mirrored);
+        assert_eq!(mode(""), Mode::Nat);
+        assert_eq!(mode("[wsl2]\nmemory=4GB\n"), Mode::Nat);
+        assert_eq!(
+            mode("[experimental]\nnetworkingMode=mirrored\n"),
+            Mode::Mirrored
+        );
+        assert_eq!(
+            mode("[wsl2]\nnetworkingMode=bridged\nvmSwitch=LAN\n"),
+            Mode::Bridged
+        );
+        assert_eq!(
+            mode("[wsl2]\nnetworkingMode=VirtioProxy\n"),
+            Mode::Other("virtioproxy".to_owned())
+        );
+    }
+}
@@ -19,11 +19,12 @@ fn main() {
 mod app {
     use crate::ui;
     use main as lib;
-    use serde::{Deserialize, Serialize};
-    use serde_json;
     use std::sync::mpsc;
     use std::sync::RwLock;
 
+    /// entries shown under recent changes
+    const HISTORY_LIMIT: usize = 50;
+
     pub enum Cmd {
         AddName(String),
         Content(String),
@@ -31,6 +32,7 @@ mod app {
         Distros(Vec<String>),
         SetDistro(String),
         Error(String),
+        History,
         None,
         OnInit,
         Preview,
@@ -39,58 +41,83 @@ mod app {
         RemoveName(String),
         SaveConfig,
         SetHostsFile(String),
-        State(lib::Config),
-        Write,
+        State(Box<lib::Config>),
+        /// write in the background and wake the window through the sender when done
+        Write(nwg::NoticeSender),
     }
-    #[derive(Serialize, Deserialize)]
-    struct SaveConfig {
-        hosts_path: String,
-        domains: Vec<String>,
-        distro: Option<String>,
+
+    fn save_config(config: &lib::Config) -> Result<(), String> {
+        lib::settings::save(config, &lib::settings::path()?)
     }
 
-    const SAVE_NAME: &str = ".wsl2-ip-host.json";
+    fn read_config() -> Result<lib::Config, String> {
+        let path = lib::settings::path()?;
+        if path.exists() {
+            return lib::settings::read(&path);
+        }
 
-    fn save_config(config: &lib::Config) -> Result<(), String> {
-        let save = {
-            SaveConfig {
-                hosts_path: config.hosts_path.to_owned(),
-                domains: config.names.to_owned(),
-                distro: config.distro.to_owned(),
-            }
-        };
+        let mut config = lib::Config::new();
+        config.add_name(lib::DEFAULT_HOST.to_owned());
 
-        let json = serde_json::to_string_pretty(&save).map_err(|e| format!("{}", e))?;
-        let path = save_path()?;
+        Ok(config)
+    }
 
-        std::fs::write(path, json).map_err(|e| format!("{}", e))
+    /// the hosts file as it would be written, followed by any other target under a heading
+    fn preview_text(plans: &[(String, lib::target::Plan)], unresolved: &[String]) -> String {
+        let warnings = unresolved.iter().map(|w| format!("# {}", w));
+        let plans = plans.iter().enumerate().map(|(i, (name, plan))| match i {
+            0 => plan.lines().join("\r\n"),
+            _ => format!("\r\n# {}\r\n{}", name, plan.lines().join("\r\n")),
+        });
+
+        warnings.chain(plans).collect::<Vec<String>>().join("\r\n")
     }
 
-    fn save_path() -> Result<std::path::PathBuf, String> {
-        match home::home_dir() {
-            Some(p) => Ok(p.join(SAVE_NAME)),
-            None => return Err("Unable to locate home folder.".to_owned()),
+    /// the latest journal entries, newest first
+    fn history_text(journal: &Option<lib::Journal>) -> Result<String, String> {
+        let journal = journal
+            .as_ref()
+            .ok_or_else(|| "Unable to locate home folder.".to_owned())?;
+        let query = lib::journal::Query {
+            limit: Some(HISTORY_LIMIT),
+            ..lib::journal::Query::default()
+        };
+
+        let entries = journal.query(&query)?;
+        if entries.is_empty() {
+            return Ok("Nothing has been written yet.".to_owned());
         }
+
+        Ok(entries
+            .iter()
+            .rev()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>()
+            .join("\r\n"))
     }
 
-    fn read_config() -> Result<lib::Config, String> {
-        let path = save_path()?;
-        let config = if let Ok(content) = std::fs::read(path) {
-            let state: SaveConfig =
-                serde_json::from_slice(&content).map_err(|e| format!("{}", e))?;
-            let mut config = lib::Config::with_hosts_path(&state.hosts_path);
-            config.names = state.domains.to_owned();
-            config.distro = state.distro;
-
-            config
-        } else {
-            let mut config = lib::Config::new();
-            config.add_name(lib::DEFAULT_HOST.to_owned());
-
-            config
+    /// the status line for a write of the settings in `config`
+    fn write_text(
+        discovery: &lib::Discovery,
+        config: &lib::Config,
+        journal: Option<&lib::Journal>,
+    ) -> String {
+        let synced = match lib::sync(discovery, config, journal) {
+            Ok(synced) => synced,
+            Err(e) => return e,
         };
 
-        Ok(config)
+        let mut lines = vec![];
+        if let (false, Some(found)) = (synced.skipped, &synced.found) {
+            lines.push(format!(
+                "Saved {} (found with {}).",
+                synced.ip(),
+                found.strategy
+            ));
+            notify(synced.ip(), &config.expanded_names());
+        }
+        lines.extend(synced.warnings());
+        lines.join("\r\n")
     }
 
     fn notify(ip: &str, domains: &Vec<String>) {
@@ -110,21 +137,37 @@ mod app {
             .show()
         {
             Ok(_) => (),
-            Err(_) => (),
+            Err(e) => log::warn!("Unable to show notification: {}", e),
         }
     }
 
     pub fn run() -> Result<(), String> {
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        let log = lib::logging::Options::from_args(&mut args);
+        // without a console a log that can't be opened goes unreported
+        let _ = lib::logging::init("wsl2-ip-host", &log);
+
         let writer = lib::find_writer();
         let unable_to_read = "Unable to read app state.";
-        let state = read_config().unwrap_or(lib::Config::new());
+        let state = read_config().unwrap_or_else(|e| {
+            log::error!("{}", e);
+            lib::Config::new()
+        });
         let state = RwLock::new(state);
-        let distros = lib::find_wsl_distros().unwrap_or(vec![]);
+        let distros = lib::find_wsl_distros().unwrap_or_else(|e| {
+            log::error!("{}", e);
+            vec![]
+        });
+        // discovery runs with a timeout so a hung distro can't freeze this loop, and
+        // anything still in flight is abandoned on quit
+        let discovery = lib::Discovery::new();
+        let journal = lib::Journal::open_default().ok();
         let (cmd_tx, cmd_rx) = mpsc::channel();
         let (main_tx, main_rx) = mpsc::channel();
+        let (written_tx, written_rx) = mpsc::channel();
 
         let handle = std::thread::spawn(|| {
-            ui::begin(cmd_tx, main_rx);
+            ui::begin(cmd_tx, main_rx, written_rx);
         });
 
         while let Ok(cmd) = cmd_rx.recv() {
@@ -133,20 +176,25 @@ mod app {
                     Ok(s) => {
                         main_tx.send(Cmd::InitOk).unwrap();
                         main_tx.send(Cmd::Distros(distros.to_owned())).unwrap();
-                        main_tx.send(Cmd::State(s.clone())).unwrap();
+                        main_tx.send(Cmd::State(Box::new(s.clone()))).unwrap();
                         match &writer {
                             Some(w) => main_tx.send(Cmd::Error(w.to_owned())).unwrap(),
                             None => main_tx.send(Cmd::None).unwrap(),
                         };
 
-                        if std::env::args().any(|a| a == "--run") {
-                            match lib::find_wsl_ip(&s.distro) {
-                                Ok(ip) => match lib::write_changes(&ip, &s) {
-                                    Ok(_) => notify(&ip, &s.names),
-                                    Err(_) => (),
-                                },
-                                _ => (),
-                            };
+                        if args.iter().any(|a| a == "--run") {
+                            let discovery = discovery.for_config(&s);
+                            let s = s.clone();
+                            let journal = journal.clone();
+                            std::thread::spawn(move || {
+                                // sync logs the error, there is no window to show it in yet
+                                if let Ok(synced) = lib::sync(&discovery, &s, journal.as_ref()) {
+                                    synced.warnings().iter().for_each(|w| log::warn!("{}", w));
+                                    if false == synced.skipped {
+                                        notify(synced.ip(), &s.expanded_names());
+                                    }
+                                }
+                            });
                         }
                     }
                     _ => main_tx
@@ -165,7 +213,7 @@ mod app {
                     }
 
                     match state.read() {
-                        Ok(s) => main_tx.send(Cmd::State(s.clone())).unwrap(),
+                        Ok(s) => main_tx.send(Cmd::State(Box::new(s.clone()))).unwrap(),
                         _ => main_tx.send(Cmd::None).unwrap(),
                     };
                 }
@@ -176,7 +224,7 @@ mod app {
                     }
 
                     match state.read() {
-                        Ok(s) => main_tx.send(Cmd::State(s.clone())).unwrap(),
+                        Ok(s) => main_tx.send(Cmd::State(Box::new(s.clone()))).unwrap(),
                         _ => main_tx.send(Cmd::None).unwrap(),
                     };
                 }
@@ -184,7 +232,7 @@ mod app {
                 Cmd::SetHostsFile(path) => match state.write() {
                     Ok(mut s) => {
                         s.set_hosts_path(&path);
-                        main_tx.send(Cmd::State(s.clone())).unwrap();
+                        main_tx.send(Cmd::State(Box::new(s.clone()))).unwrap();
                     }
                     _ => main_tx.send(Cmd::Error(unable_to_read.to_owned())).unwrap(),
                 },
@@ -200,22 +248,32 @@ mod app {
                 },
 
                 Cmd::Preview => match state.read() {
-                    Ok(s) => match lib::find_wsl_ip(&s.distro) {
-                        Ok(ip) => match s.preview(&ip) {
-                            Ok(l) => main_tx.send(Cmd::Content(l.join("\r\n"))).unwrap(),
+                    Ok(s) => {
+                        let discovery = discovery.for_config(&s);
+                        let preview = discovery
+                            .find_ip(&s.distro)
+                            .and_then(|ip| lib::preview_targets(&discovery, &ip, &s));
+                        match preview {
+                            Ok((plans, unresolved)) => main_tx
+                                .send(Cmd::Content(preview_text(&plans, &unresolved)))
+                                .unwrap(),
                             Err(s) => main_tx.send(Cmd::Error(s)).unwrap(),
-                        },
-                        Err(s) => main_tx.send(Cmd::Error(s)).unwrap(),
-                    },
+                        };
+                    }
                     _ => main_tx.send(Cmd::Error(unable_to_read.to_owned())).unwrap(),
                 },
 
+                Cmd::History => match history_text(&journal) {
+                    Ok(text) => main_tx.send(Cmd::Content(text)).unwrap(),
+                    Err(e) => main_tx.send(Cmd::Error(e)).unwrap(),
+                },
+
                 Cmd::SaveConfig => match state.read() {
                     Ok(s) => match save_config(&s) {
                         Ok(()) => main_tx
                             .send(Cmd::Content(format!(
                                 "saved to {}",
-                                save_path().unwrap().to_str().unwrap().to_owned()
+                                lib::settings::path().unwrap().to_str().unwrap().to_owned()
                             )))
                             .unwrap(),
                         Err(e) => main_tx.send(Cmd::Content(format!("{}", e))).unwrap(),
@@ -225,23 +283,29 @@ mod app {
                         .unwrap(),
                 },
 
-                Cmd::Write => match state.read() {
-                    Ok(s) => match lib::find_wsl_ip(&s.distro) {
-                        Ok(ip) => match lib::write_changes(&ip, &s) {
-                            Ok(()) => {
-                                main_tx.send(Cmd::Content("Saved.".to_owned())).unwrap();
-                                notify(&ip, &s.names);
-                            }
-                            Err(e) => main_tx.send(Cmd::Content(e.to_owned())).unwrap(),
-                        },
-                        Err(s) => main_tx.send(Cmd::Content(s)).unwrap(),
-                    },
-                    _ => main_tx
-                        .send(Cmd::Content(unable_to_read.to_owned()))
-                        .unwrap(),
+                // a write can wait minutes on the elevation prompt, so it runs on its
+                // own thread rather than holding up this loop and the window
+                Cmd::Write(done) => match state.read() {
+                    Ok(s) => {
+                        let discovery = discovery.for_config(&s);
+                        let s = s.clone();
+                        let journal = journal.clone();
+                        let written_tx = written_tx.clone();
+                        std::thread::spawn(move || {
+                            written_tx
+                                .send(write_text(&discovery, &s, journal.as_ref()))
+                                .ok();
+                            done.notice();
+                        });
+                    }
+                    _ => {
+                        written_tx.send(unable_to_read.to_owned()).ok();
+                        done.notice();
+                    }
                 },
 
                 Cmd::Quit => {
+                    discovery.cancel.cancel();
                     break;
                 }
                 _ => {}
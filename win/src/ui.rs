@@ -6,9 +6,9 @@ use crate::app::Cmd;
 const ICON_DATA: &[u8] = std::include_bytes!("./../../resources/icon.ico");
 
 // pub fn begin(tx: std::sync::mpsc::Sender<Cmd>, rx: std::sync::mpsc::Receiver<Cmd>) {
-pub fn begin(tx: mpsc::Sender<Cmd>, rx: mpsc::Receiver<Cmd>) {
+pub fn begin(tx: mpsc::Sender<Cmd>, rx: mpsc::Receiver<Cmd>, written: mpsc::Receiver<String>) {
     nwg::init().expect("Failed to init Native Windows GUI");
-    let _ui = Main::build_ui(Main::new(tx, rx)).expect("Failed to build UI");
+    let _ui = Main::build_ui(Main::new(tx, rx, written)).expect("Failed to build UI");
     nwg::dispatch_thread_events();
 }
 
@@ -28,6 +28,9 @@ pub struct Main {
     status: nwg::StatusBar,
     tx: mpsc::Sender<Cmd>,
     rx: mpsc::Receiver<Cmd>,
+    written: nwg::Notice,
+    written_rx: mpsc::Receiver<String>,
+    writing: std::cell::Cell<bool>,
 }
 
 #[derive(Default)]
@@ -85,6 +88,7 @@ pub struct PreviewUi {
 pub struct MenuUi {
     main: nwg::Menu,
     save: nwg::MenuItem,
+    history: nwg::MenuItem,
     about: nwg::MenuItem,
     sep: nwg::MenuSeparator,
     quit: nwg::MenuItem,
@@ -113,6 +117,7 @@ pub struct Systray {
     tray_menu: nwg::Menu,
     tray_run: nwg::MenuItem,
     tray_open: nwg::MenuItem,
+    tray_history: nwg::MenuItem,
     tray_about: nwg::MenuItem,
     tray_sep: nwg::MenuSeparator,
     tray_exit: nwg::MenuItem,
@@ -137,7 +142,11 @@ pub mod ui {
     }
 
     impl Main {
-        pub fn new(tx: mpsc::Sender<Cmd>, rx: mpsc::Receiver<Cmd>) -> Self {
+        pub fn new(
+            tx: mpsc::Sender<Cmd>,
+            rx: mpsc::Receiver<Cmd>,
+            written: mpsc::Receiver<String>,
+        ) -> Self {
             Main {
                 window: nwg::Window::default(),
                 menu_ui: MenuUi::default(),
@@ -154,6 +163,9 @@ pub mod ui {
                 about_ui: AboutUi::default(),
                 tx: tx,
                 rx: rx,
+                written: nwg::Notice::default(),
+                written_rx: written,
+                writing: std::cell::Cell::new(false),
             }
         }
 
@@ -307,6 +319,20 @@ pub mod ui {
             }
         }
 
+        fn show_history(&self) {
+            self.tx.send(Cmd::History).unwrap();
+            match self.rx.recv() {
+                Ok(Cmd::Content(s)) => {
+                    self.preview_ui.preview.set_text(&s);
+                    self.preview_ui.window.set_visible(true);
+                }
+                Ok(Cmd::Error(s)) => {
+                    self.status.set_text(0, &s);
+                }
+                _ => self.status.set_text(0, "Unknown issue."),
+            }
+        }
+
         fn save_config(&self) {
             self.tx.send(Cmd::SaveConfig).unwrap();
             if let Ok(Cmd::Content(s)) = self.rx.recv() {
@@ -315,10 +341,22 @@ pub mod ui {
         }
 
         fn write(&self) {
-            self.tx.send(Cmd::Write).unwrap();
-            if let Ok(Cmd::Content(s)) = self.rx.recv() {
+            if self.writing.replace(true) {
+                return;
+            }
+
+            self.actions_ui.write_button.set_enabled(false);
+            self.status.set_text(0, "Writing...");
+            self.tx.send(Cmd::Write(self.written.sender())).unwrap();
+        }
+
+        fn on_written(&self) {
+            if let Ok(s) = self.written_rx.try_recv() {
                 self.status.set_text(0, &s);
             }
+
+            self.writing.set(false);
+            self.actions_ui.write_button.set_enabled(true);
         }
 
         fn show_menu(&self) {
@@ -357,6 +395,11 @@ pub mod ui {
                 .parent(&data.main)
                 .build(&mut data.save)?;
 
+            nwg::MenuItem::builder()
+                .text("Recent Changes")
+                .parent(&data.main)
+                .build(&mut data.history)?;
+
             nwg::MenuItem::builder()
                 .text("About")
                 .parent(&data.main)
@@ -609,6 +652,11 @@ pub mod ui {
                 .parent(&data.tray_menu)
                 .build(&mut data.tray_open)?;
 
+            nwg::MenuItem::builder()
+                .text("Recent Changes")
+                .parent(&data.tray_menu)
+                .build(&mut data.tray_history)?;
+
             nwg::MenuItem::builder()
                 .text("About")
                 .parent(&data.tray_menu)
@@ -900,6 +948,10 @@ pub mod ui {
                 .text("")
                 .build(&mut data.status)?;
 
+            nwg::Notice::builder()
+                .parent(&data.window)
+                .build(&mut data.written)?;
+
             Systray::build_partial(&mut data.tray, Some(&data.window))?;
             Options::build_partial(&mut data.options, Some(&data.options_frame))?;
             ActionsUi::build_partial(&mut data.actions_ui, Some(&data.actions_frame))?;
@@ -981,12 +1033,16 @@ pub mod ui {
                                     Main::write(&evt_ui);
                                 } else if &handle == &evt_ui.tray.tray_open {
                                     Main::open(&evt_ui);
+                                } else if &handle == &evt_ui.tray.tray_history {
+                                    Main::show_history(&evt_ui);
                                 } else if &handle == &evt_ui.tray.tray_about {
                                     Main::about(&evt_ui);
                                 } else if &handle == &evt_ui.tray.tray_exit {
                                     Main::on_exit(&evt_ui);
                                 } else if &handle == &evt_ui.menu_ui.save {
                                     Main::save_config(&evt_ui);
+                                } else if &handle == &evt_ui.menu_ui.history {
+                                    Main::show_history(&evt_ui);
                                 } else if &handle == &evt_ui.menu_ui.about {
                                     Main::about(&evt_ui);
                                 } else if &handle == &evt_ui.menu_ui.quit {
@@ -998,6 +1054,11 @@ pub mod ui {
                                     Main::on_init(&evt_ui);
                                 }
                             }
+                            Event::OnNotice => {
+                                if &handle == &evt_ui.written.handle {
+                                    Main::on_written(&evt_ui);
+                                }
+                            }
                             _ => {}
                         }
                     }
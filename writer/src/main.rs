@@ -16,27 +16,109 @@ fn main() {
 mod app {
     use main as lib;
 
+    const NAME: &str = "wsl2-ip-host-writer";
+
+    /// what the writer was asked to do
+    enum Request {
+        Write(lib::Config, lib::target::Update),
+        Remove(lib::Config),
+    }
+
     pub fn run() -> Result<(), String> {
-        let args = std::env::args();
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        let options = lib::logging::Options::from_args(&mut args);
+        // there is no console, so when the log can't be opened there is nowhere to say so
+        let _ = lib::logging::init(NAME, &options);
+
+        let request = match parse(&args) {
+            Ok(request) => request,
+            Err(e) => {
+                log::error!("rejected `{}`: {}", args.join(" "), e);
+                return Err(e);
+            }
+        };
+        log::info!("accepted `{}`", args.join(" "));
 
-        if args.len() != 4 {
+        let result = match request {
+            Request::Write(config, update) => save(&config, &update),
+            Request::Remove(config) => remove(&config),
+        };
+        if let Err(e) = &result {
+            log::error!("failed: {}", e);
+        }
+
+        result
+    }
+
+    fn parse(args: &[String]) -> Result<Request, String> {
+        if let [flag, path] = args {
+            if flag == "--remove" {
+                return Ok(Request::Remove(lib::Config::with_hosts_path(path)));
+            }
+        }
+
+        if args.len() < 3 {
             return Err("Insufficient arguments provided.".to_owned());
         }
 
-        let mut i = args.skip(1);
+        let mut i = args.iter();
         let ip = i.next().unwrap();
         let domains = i.next().unwrap();
         let path = i.next().unwrap();
 
-        save(&path, &domains, &ip)
-    }
+        if ip.parse::<std::net::IpAddr>().is_err() {
+            return Err(format!("{} is not an IP address.", ip));
+        }
 
-    fn save(path: &str, domains: &str, ip: &str) -> Result<(), String> {
         let mut config = lib::Config::with_hosts_path(path);
-        for d in domains.split(",") {
+        // a lone comma is sent when every name has another source
+        for d in domains.split(",").filter(|d| false == d.is_empty()) {
             config.add_name(d.to_owned());
         }
+        let mut others: Vec<lib::Mapping> = vec![];
+
+        // optional flags follow the positional arguments
+        while let Some(flag) = i.next() {
+            match (&flag[..], i.next()) {
+                ("--forward", Some(value)) => config.port_forwards.push(value.parse()?),
+                ("--on-conflict", Some(value)) => config.on_conflict = value.parse()?,
+                ("--line-style", Some(value)) => config.line_style = value.parse()?,
+                ("--static", Some(value)) => config.static_entries.push(value.parse()?),
+                ("--map", Some(value)) => others.push(value.parse()?),
+                _ => return Err(format!("Unknown argument {}", flag)),
+            };
+        }
+
+        config.check()?;
+        let mut update = lib::target::Update::new(&config, ip);
+        update.others = others;
+        Ok(Request::Write(config, update))
+    }
+
+    fn save(config: &lib::Config, update: &lib::target::Update) -> Result<(), String> {
+        for target in config.targets() {
+            if target.write(update)? {
+                log::info!("wrote {} to {}", update.ip, target.describe());
+            }
+        }
+
+        for change in lib::apply_port_forwards(&update.ip, &config.port_forwards)? {
+            log::info!("{}", change);
+        }
+
+        Ok(())
+    }
+
+    fn remove(config: &lib::Config) -> Result<(), String> {
+        for target in config.targets() {
+            target.remove()?;
+            log::info!("removed our lines from {}", target.describe());
+        }
+
+        for change in lib::remove_port_forwards()? {
+            log::info!("{}", change);
+        }
 
-        config.write_file(ip)
+        Ok(())
     }
 }